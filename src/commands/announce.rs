@@ -0,0 +1,45 @@
+use crate::BotData;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, CreateEmbed,
+    ResolvedValue,
+};
+use serenity::builder::CreateCommand;
+use serenity::model::application::ResolvedOption;
+
+pub async fn run(
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> CreateEmbed {
+    let guild_id = match command.guild_id {
+        Some(g) => g,
+        None => return CreateEmbed::new().title("❌ Not in a guild"),
+    };
+
+    let enabled = match options.first().map(|o| &o.value) {
+        Some(ResolvedValue::Boolean(b)) => *b,
+        _ => return CreateEmbed::new().title("❌ Missing option"),
+    };
+
+    let data_read = ctx.data.read().await;
+    let bot_user = data_read.get::<BotData>().expect("BotData missing");
+
+    let mut disabled = bot_user.announce_disabled.lock().await;
+    if enabled {
+        disabled.remove(&guild_id);
+    } else {
+        disabled.insert(guild_id);
+    }
+
+    let status_text = if enabled { "enabled" } else { "disabled" };
+    CreateEmbed::new().title(format!("📢 Now-playing messages **{}**", status_text))
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("announce")
+        .description("Turn the automatic now-playing messages on or off")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Boolean, "enabled", "Turn announcements on or off")
+                .required(true),
+        )
+}
@@ -0,0 +1,35 @@
+use crate::BotData;
+use serenity::all::{CommandInteraction, Context, CreateEmbed};
+use serenity::builder::CreateCommand;
+
+pub async fn run(ctx: &Context, command: &CommandInteraction) -> CreateEmbed {
+    let guild_id = match command.guild_id {
+        Some(g) => g.to_string(),
+        None => return CreateEmbed::new().title("❌ Not in a guild"),
+    };
+
+    let data_read = ctx.data.read().await;
+    let voice_api = data_read
+        .get::<BotData>()
+        .expect("BotData missing")
+        .voice_api
+        .clone();
+
+    let player = match voice_api.get_player(&guild_id).await {
+        Some(p) => p,
+        None => return CreateEmbed::new()
+            .title("❌ Not connected to voice")
+            .description("Use `/play` to connect the bot to a voice channel"),
+    };
+
+    let queue = player.get_queue();
+
+    let count = queue.clear().await;
+    CreateEmbed::new()
+        .title("🧹 Queue cleared")
+        .description(format!("Removed {} tracks", count))
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("clear").description("Remove all upcoming tracks from the queue")
+}
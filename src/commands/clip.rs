@@ -0,0 +1,75 @@
+use crate::{BotData, CommandResponse};
+use crate::discord_voice_api::voice::clip::CLIP_SECONDS;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateAttachment, CreateCommandOption,
+    CreateEmbed, CreateEmbedAuthor, ResolvedValue,
+};
+use serenity::builder::CreateCommand;
+use serenity::model::application::ResolvedOption;
+
+pub async fn run(
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> CommandResponse {
+    let guild_id = match command.guild_id {
+        Some(g) => g.to_string(),
+        None => return CommandResponse::Embed(CreateEmbed::new().title("❌ Not in a guild")),
+    };
+
+    let seconds = match options.first().map(|o| &o.value) {
+        Some(ResolvedValue::Integer(s)) => (*s as u64).clamp(1, CLIP_SECONDS),
+        _ => CLIP_SECONDS,
+    };
+
+    let data_read = ctx.data.read().await;
+    let voice_api = data_read
+        .get::<BotData>()
+        .expect("BotData missing")
+        .voice_api
+        .clone();
+
+    let player = match voice_api.get_player(&guild_id).await {
+        Some(p) => p,
+        None => {
+            return CommandResponse::Embed(
+                CreateEmbed::new()
+                    .title("❌ Not connected to voice")
+                    .description("Use `/play` to connect the bot to a voice channel"),
+            );
+        }
+    };
+
+    match player.clip(seconds).await {
+        Ok(ogg) => {
+            let filename = format!(
+                "clip_{}.ogg",
+                chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")
+            );
+            let embed = CreateEmbed::new()
+                .title(format!("🎬 Clipped the last {}s", seconds))
+                .author(CreateEmbedAuthor::new("Rusty MetalFistBot 7000"))
+                .color(0xFF972C);
+            CommandResponse::File(embed, CreateAttachment::bytes(ogg, filename))
+        }
+        Err(e) => CommandResponse::Embed(
+            CreateEmbed::new()
+                .title("❌ Could not create clip")
+                .description(e.to_string()),
+        ),
+    }
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("clip")
+        .description("Save the last seconds of the voice channel as an audio file")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "seconds",
+                "How many seconds to clip (default 30)",
+            )
+            .min_int_value(1)
+            .max_int_value(CLIP_SECONDS),
+        )
+}
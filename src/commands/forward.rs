@@ -0,0 +1,30 @@
+use crate::commands::seek::{SeekTarget, seek};
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, CreateEmbed,
+    ResolvedValue,
+};
+use serenity::builder::CreateCommand;
+use serenity::model::application::ResolvedOption;
+
+pub async fn run(
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> CreateEmbed {
+    let seconds = match options.first().map(|o| &o.value) {
+        Some(ResolvedValue::Integer(s)) => *s as f64,
+        _ => return CreateEmbed::new().title("❌ Missing number of seconds"),
+    };
+
+    seek(ctx, command, SeekTarget::Relative(seconds)).await
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("forward")
+        .description("Skip ahead in the current track")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Integer, "seconds", "Seconds to skip ahead")
+                .min_int_value(1)
+                .required(true),
+        )
+}
@@ -0,0 +1,65 @@
+use crate::BotData;
+use serenity::all::{
+    CommandInteraction, Context, CreateEmbed, CreateEmbedAuthor, CreateEmbedFooter,
+};
+use serenity::builder::CreateCommand;
+use std::time::UNIX_EPOCH;
+
+const HISTORY_LENGTH: usize = 20;
+
+pub async fn run(ctx: &Context, command: &CommandInteraction) -> CreateEmbed {
+    let guild_id = match command.guild_id {
+        Some(g) => g.to_string(),
+        None => return CreateEmbed::new().title("❌ Not in a guild"),
+    };
+
+    let data_read = ctx.data.read().await;
+    let bot_user = match data_read.get::<BotData>() {
+        Some(b) => b,
+        None => return CreateEmbed::new().title("❌ Bot data not found"),
+    };
+
+    let player = match bot_user.voice_api.get_player(&guild_id).await {
+        Some(p) => p,
+        None => {
+            return CreateEmbed::new()
+                .title("❌ Not connected to voice")
+                .description("Use `/play` to connect the bot to a voice channel");
+        }
+    };
+
+    let history = player.get_queue().history(HISTORY_LENGTH).await;
+    if history.is_empty() {
+        return CreateEmbed::new()
+            .title("📜 Nothing played yet")
+            .description("Add songs with `/play`!");
+    }
+
+    let mut desc = String::new();
+    for (i, entry) in history.iter().enumerate() {
+        let played_at = entry
+            .played_at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        desc.push_str(&format!("**{}.** {} <t:{}:R>", i + 1, entry.track.title, played_at));
+        if let Some(user_id) = &entry.track.requested_by {
+            desc.push_str(&format!(" · <@{}>", user_id));
+        }
+        desc.push('\n');
+    }
+
+    CreateEmbed::new()
+        .title("📜 Recently played")
+        .description(desc)
+        .color(0xFF972C)
+        .footer(
+            CreateEmbedFooter::new(format!("Requested by {}", command.user.name))
+                .icon_url(bot_user.bot_pfp_url.clone()),
+        )
+        .author(CreateEmbedAuthor::new("Rusty MetalFistBot 7000"))
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("history").description("Show the most recently played tracks")
+}
@@ -1,15 +1,30 @@
+use crate::BotData;
 use serenity::all::{CommandInteraction, Context};
 use serenity::builder::CreateCommand;
 use serenity::model::application::ResolvedOption;
-use std::error::Error;
 
 pub async fn run(
     ctx: &Context,
     command: &CommandInteraction,
     _options: &[ResolvedOption<'_>],
 ) -> String {
-    
-    "Left the voice channel".to_string()
+    let guild_id = match command.guild_id {
+        Some(g) => g.to_string(),
+        None => return "This command only works in a guild".to_string(),
+    };
+
+    let data_read = ctx.data.read().await;
+    let voice_api = data_read
+        .get::<BotData>()
+        .expect("BotData missing")
+        .voice_api
+        .clone();
+
+    match voice_api.leave(&guild_id).await {
+        Ok(true) => "Left the voice channel".to_string(),
+        Ok(false) => "I'm not connected to a voice channel".to_string(),
+        Err(e) => format!("Could not leave the voice channel: {}", e),
+    }
 }
 
 pub fn register() -> CreateCommand {
@@ -0,0 +1,58 @@
+use crate::BotData;
+use crate::discord_voice_api::voice::player::LoopMode;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, CreateEmbed,
+    ResolvedValue,
+};
+use serenity::builder::CreateCommand;
+use serenity::model::application::ResolvedOption;
+
+pub async fn run(
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> CreateEmbed {
+    let guild_id = match command.guild_id {
+        Some(g) => g.to_string(),
+        None => return CreateEmbed::new().title("❌ Not in a guild"),
+    };
+
+    let data_read = ctx.data.read().await;
+    let voice_api = data_read
+        .get::<BotData>()
+        .expect("BotData missing")
+        .voice_api
+        .clone();
+
+    let player = match voice_api.get_player(&guild_id).await {
+        Some(p) => p,
+        None => return CreateEmbed::new()
+            .title("❌ Not connected to voice")
+            .description("Use `/play` to connect the bot to a voice channel"),
+    };
+
+    let queue = player.get_queue();
+
+    let mode = match options.first().map(|o| &o.value) {
+        Some(ResolvedValue::String("off")) => LoopMode::Off,
+        Some(ResolvedValue::String("track")) => LoopMode::Track,
+        Some(ResolvedValue::String("queue")) => LoopMode::Queue,
+        // No mode given: cycle through them
+        _ => queue.loop_mode().await.next(),
+    };
+
+    queue.set_loop_mode(mode).await;
+
+    CreateEmbed::new().title(format!("Loop mode: **{}**", mode.label()))
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("loop")
+        .description("Loop the current track or the whole queue")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "mode", "Loop mode (cycles if omitted)")
+                .add_string_choice("Off", "off")
+                .add_string_choice("Track", "track")
+                .add_string_choice("Queue", "queue"),
+        )
+}
@@ -1,14 +1,33 @@
+pub mod announce;
+pub mod clear;
+pub mod clip;
+pub mod dedupe;
 pub mod dick_size;
+pub mod forward;
+pub mod history;
 pub mod leave;
+pub mod loop_mode;
+pub mod move_track;
 pub mod neko;
 pub mod nowplaying;
 pub mod pause;
 pub mod ping;
 pub mod play;
+pub mod playnext;
+pub mod previous;
 pub mod queue;
 pub mod rand_quote;
+pub mod record;
+pub mod remove;
 pub mod resume;
+pub mod rewind;
 pub mod roast;
+pub mod search;
+pub mod seek;
 pub mod serverinfo;
+pub mod shuffle;
 pub mod skip;
+pub mod volume;
 pub mod bass_boost;
+pub mod nightcore;
+pub mod vaporwave;
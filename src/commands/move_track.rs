@@ -0,0 +1,75 @@
+use crate::BotData;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, CreateEmbed,
+    ResolvedValue,
+};
+use serenity::builder::CreateCommand;
+use serenity::model::application::ResolvedOption;
+
+pub async fn run(
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> CreateEmbed {
+    let position = |name: &str| {
+        options.iter().find(|o| o.name == name).and_then(|o| match o.value {
+            ResolvedValue::Integer(p) => Some(p),
+            _ => None,
+        })
+    };
+    let (from, to) = match (position("from"), position("to")) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return CreateEmbed::new().title("❌ Missing queue positions"),
+    };
+
+    let guild_id = match command.guild_id {
+        Some(g) => g.to_string(),
+        None => return CreateEmbed::new().title("❌ Not in a guild"),
+    };
+
+    let data_read = ctx.data.read().await;
+    let voice_api = data_read
+        .get::<BotData>()
+        .expect("BotData missing")
+        .voice_api
+        .clone();
+
+    let player = match voice_api.get_player(&guild_id).await {
+        Some(p) => p,
+        None => return CreateEmbed::new()
+            .title("❌ Not connected to voice")
+            .description("Use `/play` to connect the bot to a voice channel"),
+    };
+
+    let queue = player.get_queue();
+
+    let len = queue.len().await;
+    let in_range = |p: i64| p >= 1 && p as usize <= len;
+    if !in_range(from) || !in_range(to) {
+        return CreateEmbed::new()
+            .title("❌ Invalid queue position")
+            .description(format!("The queue has {} tracks", len));
+    }
+
+    match queue.move_track(from as usize - 1, to as usize - 1).await {
+        Some(track) => CreateEmbed::new()
+            .title("↕️ Moved track")
+            .description(format!("**{}** from {} to {}", track.title, from, to)),
+        None => CreateEmbed::new().title("❌ Invalid queue position"),
+    }
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("move")
+        .description("Move a track to another position in the queue")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Integer, "from", "Current position in `/queue`")
+                .min_int_value(1)
+                .required(true),
+        )
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Integer, "to", "New position")
+                .min_int_value(1)
+                .required(true),
+        )
+}
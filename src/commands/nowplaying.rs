@@ -1,61 +1,443 @@
-use crate::BotData;
+use crate::commands::previous::go_back;
+use crate::commands::seek::format_timestamp;
+use crate::discord_voice_api::voice::audio_commands::AudioCommand;
+use crate::discord_voice_api::voice::events::TrackEvent;
+use crate::discord_voice_api::voice::player::{AudioPlayer, PlaybackPosition};
+use crate::{BotData, CommandResponse};
 use serenity::all::{
-    CommandInteraction, Context, CreateEmbed, CreateEmbedAuthor, CreateEmbedFooter,
+    ButtonStyle, ChannelId, CommandInteraction, ComponentInteraction, Context, CreateActionRow,
+    CreateButton, CreateEmbed, CreateEmbedAuthor, CreateEmbedFooter, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, EditMessage, GuildId, Message, MessageId,
 };
 use serenity::builder::CreateCommand;
 use serenity::model::application::ResolvedOption;
-use std::error::Error;
+use std::sync::{Arc, Weak};
+use std::time::UNIX_EPOCH;
+use tokio::sync::broadcast;
+
+const PROGRESS_BAR_WIDTH: usize = 20;
+const MAX_ERROR_LENGTH: usize = 200;
+
+/// Custom ID prefix of the player control buttons
+pub const BUTTON_PREFIX: &str = "player:";
+
+/// `▬▬▬▬🔘▬▬▬▬▬` style bar, the knob marking the elapsed share of `total`
+fn progress_bar(elapsed: f64, total: f64) -> String {
+    let ratio = if total > 0.0 { (elapsed / total).clamp(0.0, 1.0) } else { 0.0 };
+    let knob = ((ratio * PROGRESS_BAR_WIDTH as f64) as usize).min(PROGRESS_BAR_WIDTH - 1);
+    (0..PROGRESS_BAR_WIDTH)
+        .map(|i| if i == knob { "🔘" } else { "▬" })
+        .collect()
+}
+
+pub fn control_buttons(paused: bool) -> Vec<CreateActionRow> {
+    let button = |action: &str, emoji: char, label: &str| {
+        CreateButton::new(format!("{}{}", BUTTON_PREFIX, action))
+            .emoji(emoji)
+            .label(label)
+            .style(ButtonStyle::Secondary)
+    };
+    let pause = if paused {
+        button("pause", '▶', "Resume")
+    } else {
+        button("pause", '⏸', "Pause")
+    };
+
+    vec![
+        CreateActionRow::Buttons(vec![
+            button("previous", '⏮', "Previous"),
+            pause.style(ButtonStyle::Primary),
+            button("skip", '⏭', "Skip"),
+        ]),
+        CreateActionRow::Buttons(vec![
+            button("loop", '🔁', "Loop"),
+            button("shuffle", '🔀', "Shuffle"),
+            button("stop", '⏹', "Stop").style(ButtonStyle::Danger),
+        ]),
+    ]
+}
+
+/// The now-playing embed. `position` is passed in so callers can render
+/// a state the producer has not picked up yet.
+pub async fn now_playing_embed(
+    player: &AudioPlayer,
+    position: &PlaybackPosition,
+    footer_text: &str,
+    bot_pfp_url: &str,
+) -> CreateEmbed {
+    let queue = player.get_queue();
+
+    let current_track = match queue.get_current_track().await {
+        Some(track) => track,
+        None => {
+            return CreateEmbed::new()
+                .title("🎵 No track currently playing")
+                .description("Add songs with `/play`!");
+        }
+    };
+
+    let title = &current_track.title;
+    let mut desc = format!("[{}]({})\n", title, current_track.page_url());
+    if let Some(artist) = current_track.artist() {
+        desc.push_str(&format!("by **{}**\n", artist));
+    }
+    if current_track.live() {
+        desc.push_str("🔴 **LIVE**\n");
+    }
+    if let Some(user_id) = &current_track.requested_by {
+        desc.push_str(&format!("Requested by <@{}>", user_id));
+        if let Some(at) = current_track.requested_at.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+            desc.push_str(&format!(" <t:{}:R>", at.as_secs()));
+        }
+        desc.push('\n');
+    }
+    let loop_mode = queue.loop_mode().await;
+
+    let state_icon = if position.paused { "⏸" } else { "▶" };
+    let elapsed = format_timestamp(position.seconds);
+    match current_track.known_duration() {
+        Some(total) => {
+            desc.push_str(&format!(
+                "\n{} {} `{} / {}`\n",
+                state_icon,
+                progress_bar(position.seconds, total),
+                elapsed,
+                format_timestamp(total)
+            ));
+        }
+        _ => desc.push_str(&format!("\n{} `{}`\n", state_icon, elapsed)),
+    }
+    if let Some(next) = &position.crossfading_into {
+        desc.push_str(&format!("🔀 Fading into **{}**\n", next));
+    }
+
+    let filter_state = player.audio_filter_state.read().await.clone();
+    let mut filters = Vec::new();
+    if filter_state.bass_boost {
+        filters.push("Bass-boost");
+    }
+    if filter_state.nightcore {
+        filters.push("Nightcore");
+    }
+    if filter_state.vaporwave {
+        filters.push("Vaporwave");
+    }
+    let filters = if filters.is_empty() { "None".to_string() } else { filters.join(", ") };
+
+    CreateEmbed::new()
+        .title("🎶 Current track")
+        .author(CreateEmbedAuthor::new("Rusty MetalFistBot 7000"))
+        .footer(
+            CreateEmbedFooter::new(footer_text)
+                .icon_url(bot_pfp_url),
+        )
+        .thumbnail(current_track.thumbnail.clone().unwrap_or_default())
+        .description(desc)
+        .field("Loop", loop_mode.label(), true)
+        .field("Filters", filters, true)
+        .field("Volume", format!("{:.0}%", filter_state.volume * 100.0), true)
+        .color(0xFF972C)
+}
 
 pub async fn run(
     ctx: &Context,
     command: &CommandInteraction,
     _options: &[ResolvedOption<'_>],
-) -> CreateEmbed {
+) -> CommandResponse {
     let guild_id = match command.guild_id {
         Some(g) => g.to_string(),
-        None => return CreateEmbed::new().title("❌ Not in a guild"),
+        None => return CommandResponse::Embed(CreateEmbed::new().title("❌ Not in a guild")),
     };
 
     let data_read = ctx.data.read().await;
     let bot_user = match data_read.get::<BotData>() {
         Some(b) => b,
-        None => return CreateEmbed::new().title("❌ Bot data not found"),
+        None => return CommandResponse::Embed(CreateEmbed::new().title("❌ Bot data not found")),
     };
 
     let player = match bot_user.voice_api.get_player(&guild_id).await {
         Some(p) => p,
         None => {
-            return CreateEmbed::new()
-                .title("❌ Not connected to voice")
-                .description("Use `/play` to connect the bot to a voice channel");
+            return CommandResponse::Embed(
+                CreateEmbed::new()
+                    .title("❌ Not connected to voice")
+                    .description("Use `/play` to connect the bot to a voice channel"),
+            );
         }
     };
 
-    let queue = player.get_queue();
+    let position = player.playback_position.read().await.clone();
+    let footer = format!("Requested by {}", command.user.name);
+    let embed = now_playing_embed(&player, &position, &footer, &bot_user.bot_pfp_url).await;
 
-    let current_track = match queue.get_current_track().await {
-        Some(track) => track.clone(),
-        None => {
-            return CreateEmbed::new()
-                .title("🎵 No track currently playing")
-                .description("Add songs with `/play`!");
+    if player.get_queue().get_current_track().await.is_none() {
+        return CommandResponse::Embed(embed);
+    }
+    CommandResponse::Components(embed, control_buttons(position.paused))
+}
+
+/// Edits the `/nowplaying` response whenever the track changes.
+/// A newer `/nowplaying` in the same guild takes over from older ones.
+pub async fn keep_updated(ctx: &Context, command: &CommandInteraction, message: Message) {
+    let Some(guild_id) = command.guild_id else {
+        return;
+    };
+
+    let data_read = ctx.data.read().await;
+    let Some(bot_user) = data_read.get::<BotData>() else {
+        return;
+    };
+    let Some(player) = bot_user.voice_api.get_player(&guild_id.to_string()).await else {
+        return;
+    };
+
+    let mut track_rx = player.get_queue().watch_current_track();
+    // Weak, so the task does not keep a disconnected player alive
+    let player = Arc::downgrade(&player);
+    let http = ctx.http.clone();
+    let footer = format!("Requested by {}", command.user.name);
+    let bot_pfp_url = bot_user.bot_pfp_url.clone();
+
+    let task = tokio::spawn(async move {
+        while track_rx.changed().await.is_ok() {
+            let Some(player) = player.upgrade() else {
+                break;
+            };
+            let playing = track_rx.borrow_and_update().is_some();
+            let position = player.playback_position.read().await.clone();
+            let embed = now_playing_embed(&player, &position, &footer, &bot_pfp_url).await;
+            let components = if playing { control_buttons(position.paused) } else { Vec::new() };
+
+            let edit = EditMessage::new().embed(embed).components(components);
+            if let Err(why) = message.channel_id.edit_message(&http, message.id, edit).await {
+                println!("Cannot update now-playing message: {why}");
+                break;
+            }
         }
+    });
+
+    if let Some(old) = bot_user.now_playing_updaters.lock().await.insert(guild_id, task) {
+        old.abort();
+    }
+}
+
+/// Posts a now-playing message in `channel_id` whenever the guild's player
+/// starts a track. Only the first call per player has an effect.
+pub async fn announce_tracks(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    player: &Arc<AudioPlayer>,
+) {
+    let data_read = ctx.data.read().await;
+    let Some(bot_user) = data_read.get::<BotData>() else {
+        return;
     };
 
-    let title = &current_track.title;
-    let url = current_track.url.as_deref().unwrap_or("unknown");
-    let desc = format!("[{}]({})\n", title, url);
+    let weak_player = Arc::downgrade(player);
+    let mut announcers = bot_user.announcers.lock().await;
+    if let Some((existing, _)) = announcers.get(&guild_id) {
+        if existing.ptr_eq(&weak_player) {
+            return;
+        }
+    }
 
-    CreateEmbed::new()
-        .title("🎶 Current track")
-        .author(CreateEmbedAuthor::new("Rusty MetalFistBot 7000"))
-        .footer(
-            CreateEmbedFooter::new(format!("Requested by {}", command.user.name))
-                .icon_url(bot_user.bot_pfp_url.clone()),
+    let task = tokio::spawn(announce_loop(
+        ctx.clone(),
+        guild_id,
+        channel_id,
+        player.subscribe_events(),
+        weak_player.clone(),
+        bot_user.bot_pfp_url.clone(),
+    ));
+    // A leftover from an earlier session in this guild
+    if let Some((_, old)) = announcers.insert(guild_id, (weak_player, task)) {
+        old.abort();
+    }
+}
+
+async fn announcements_enabled(ctx: &Context, guild_id: GuildId) -> bool {
+    let data_read = ctx.data.read().await;
+    match data_read.get::<BotData>() {
+        Some(bot_user) => !bot_user.announce_disabled.lock().await.contains(&guild_id),
+        None => false,
+    }
+}
+
+async fn announce_loop(
+    ctx: Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    mut events: broadcast::Receiver<TrackEvent>,
+    player: Weak<AudioPlayer>,
+    bot_pfp_url: String,
+) {
+    let mut message: Option<MessageId> = None;
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if !announcements_enabled(&ctx, guild_id).await {
+            continue;
+        }
+        let Some(player) = player.upgrade() else {
+            break;
+        };
+
+        let result = match event {
+            TrackEvent::Started(_) => {
+                let position = player.playback_position.read().await.clone();
+                let embed = now_playing_embed(&player, &position, "Now playing", &bot_pfp_url).await;
+                let new_message = CreateMessage::new()
+                    .embed(embed)
+                    .components(control_buttons(position.paused));
+                channel_id
+                    .send_message(&ctx.http, new_message)
+                    .await
+                    .map(|m| message = Some(m.id))
+            }
+            TrackEvent::CrossfadeStarted { to, .. } => match message {
+                Some(message_id) => {
+                    let mut position = player.playback_position.read().await.clone();
+                    position.crossfading_into = Some(to.title);
+                    let embed = now_playing_embed(&player, &position, "Now playing", &bot_pfp_url).await;
+                    channel_id
+                        .edit_message(&ctx.http, message_id, EditMessage::new().embed(embed))
+                        .await
+                        .map(|_| ())
+                }
+                None => Ok(()),
+            },
+            TrackEvent::Ended(track) => match message.take() {
+                Some(message_id) => {
+                    let embed = CreateEmbed::new()
+                        .title("⏹ Finished")
+                        .description(format!("[{}]({})", track.title, track.page_url()))
+                        .color(0xFF972C);
+                    let edit = EditMessage::new().embed(embed).components(Vec::new());
+                    channel_id
+                        .edit_message(&ctx.http, message_id, edit)
+                        .await
+                        .map(|_| ())
+                }
+                None => Ok(()),
+            },
+            TrackEvent::Errored { track, error } => {
+                let error: String = error.chars().take(MAX_ERROR_LENGTH).collect();
+                let embed = CreateEmbed::new()
+                    .title("⚠️ Could not play track")
+                    .description(format!("**{}**\n`{}`", track.title, error));
+                channel_id
+                    .send_message(&ctx.http, CreateMessage::new().embed(embed))
+                    .await
+                    .map(|_| ())
+            }
+            TrackEvent::QueueFinished => {
+                let embed = CreateEmbed::new()
+                    .title("✅ Queue finished")
+                    .description("Add songs with `/play`!");
+                channel_id
+                    .send_message(&ctx.http, CreateMessage::new().embed(embed))
+                    .await
+                    .map(|_| ())
+            }
+        };
+
+        if let Err(why) = result {
+            println!("Cannot post now-playing message: {why}");
+        }
+    }
+}
+
+/// Handles presses on the buttons from `control_buttons`
+pub async fn handle_button(ctx: &Context, component: &ComponentInteraction) {
+    let Some(action) = component.data.custom_id.strip_prefix(BUTTON_PREFIX) else {
+        return;
+    };
+
+    let response = button_response(ctx, component, action).await;
+    if let Err(why) = component.create_response(&ctx.http, response).await {
+        println!("Cannot respond to button: {why}");
+    }
+}
+
+async fn button_response(
+    ctx: &Context,
+    component: &ComponentInteraction,
+    action: &str,
+) -> CreateInteractionResponse {
+    let ephemeral = |text: &str| {
+        CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new().content(text).ephemeral(true),
         )
-        .thumbnail(current_track.thumbnail.clone().unwrap_or_default())
-        .description(desc)
-        .color(0xFF972C)
+    };
+
+    let guild_id = match component.guild_id {
+        Some(g) => g.to_string(),
+        None => return ephemeral("❌ Not in a guild"),
+    };
+
+    let data_read = ctx.data.read().await;
+    let bot_user = data_read.get::<BotData>().expect("BotData missing");
+
+    let player = match bot_user.voice_api.get_player(&guild_id).await {
+        Some(p) => p,
+        None => return ephemeral("❌ Not connected to voice"),
+    };
+
+    let queue = player.get_queue();
+    let mut position = player.playback_position.read().await.clone();
+    let playing = queue.get_current_track().await.is_some();
+
+    // Playback commands wait in the channel until the next producer runs,
+    // so they must not be sent while nothing is playing
+    let playback_cmd = match action {
+        "pause" if playing => {
+            // Rendered right away; the producer flips its own state a moment later
+            position.paused = !position.paused;
+            Some(if position.paused { AudioCommand::Pause } else { AudioCommand::Resume })
+        }
+        "skip" if playing => Some(AudioCommand::Skip),
+        "stop" if playing => {
+            queue.clear().await;
+            Some(AudioCommand::Stop)
+        }
+        "pause" | "skip" | "stop" => return ephemeral("🎵 No track currently playing"),
+        "previous" => {
+            if go_back(&player).await.is_none() {
+                return ephemeral("❌ No previous track");
+            }
+            None
+        }
+        "loop" => {
+            queue.set_loop_mode(queue.loop_mode().await.next()).await;
+            None
+        }
+        "shuffle" => {
+            queue.shuffle().await;
+            None
+        }
+        _ => return ephemeral("❌ Unknown control"),
+    };
+
+    if let Some(cmd) = playback_cmd {
+        player
+            .playback_cmd_tx
+            .send(cmd)
+            .await
+            .expect("Playback channel invalid");
+    }
+
+    let footer = format!("Requested by {}", component.user.name);
+    let embed = now_playing_embed(&player, &position, &footer, &bot_user.bot_pfp_url).await;
+    CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::new()
+            .embed(embed)
+            .components(control_buttons(position.paused)),
+    )
 }
 
 pub fn register() -> CreateCommand {
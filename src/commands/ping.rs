@@ -1,10 +1,53 @@
+use crate::{BotData, ShardManagerKey};
+use serenity::all::{CommandInteraction, Context, ShardId};
 use serenity::builder::CreateCommand;
-use serenity::model::application::ResolvedOption;
+use std::time::Duration;
 
-pub fn run(_options: &[ResolvedOption]) -> String {
-    "Hey, I'm alive!".to_string()
+fn format_latency(latency: Option<Duration>) -> String {
+    match latency {
+        Some(d) => format!("{} ms", d.as_millis()),
+        None => "n/a".to_string(),
+    }
+}
+
+pub async fn run(ctx: &Context, command: &CommandInteraction) -> String {
+    let data_read = ctx.data.read().await;
+    let voice_api = data_read
+        .get::<BotData>()
+        .expect("BotData missing")
+        .voice_api
+        .clone();
+
+    // Heartbeat round trip of the shard this interaction came in on
+    let shard_manager = data_read.get::<ShardManagerKey>().cloned();
+    let shard_latency = match shard_manager {
+        Some(manager) => manager
+            .runners
+            .lock()
+            .await
+            .get(&ShardId(ctx.shard_id.0))
+            .and_then(|runner| runner.latency),
+        None => None,
+    };
+    let gateway = format_latency(shard_latency);
+    // The separate connection voice joins (op 4) go through
+    let voice_gateway = format_latency(voice_api.gateway_latency().await);
+
+    let player = match command.guild_id {
+        Some(g) => voice_api.get_player(&g.to_string()).await,
+        None => None,
+    };
+    let voice = match player {
+        Some(p) => format_latency(p.latency()),
+        None => "not connected".to_string(),
+    };
+
+    format!(
+        "🏓 Pong!\nGateway: **{}**\nVoice gateway: **{}**\nVoice: **{}**",
+        gateway, voice_gateway, voice
+    )
 }
 
 pub fn register() -> CreateCommand {
-    CreateCommand::new("ping").description("A ping command")
+    CreateCommand::new("ping").description("Show gateway and voice latency")
 }
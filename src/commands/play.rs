@@ -1,25 +1,278 @@
 use crate::BotData;
-use crate::discord_voice_api::voice::player::Track;
+use crate::commands::nowplaying::announce_tracks;
+use crate::commands::seek::format_timestamp;
+use crate::discord_voice_api::voice::player::{AudioPlayer, Track};
+use crate::discord_voice_api::voice::resolver::AUDIO_FORMAT;
 use anyhow::Result;
 use serde_json::Value;
+use serde::Deserialize;
 use serenity::all::{
-    ChannelId, CommandInteraction, CommandOptionType, Context, CreateCommandOption, GuildId,
-    ResolvedValue,
+    ChannelId, CommandInteraction, CommandOptionType, Context, CreateAutocompleteResponse,
+    CreateCommandOption, CreateInteractionResponse, GuildId, ResolvedValue, UserId,
 };
 use serenity::builder::CreateCommand;
 use serenity::futures::StreamExt;
 use serenity::model::application::ResolvedOption;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::process::Command;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant, timeout};
+
+/// Autocomplete results per query (yt-dlp `ytsearch5:`)
+const SEARCH_RESULTS: usize = 5;
+const MIN_QUERY_LENGTH: usize = 3;
+/// Only the last keystroke within this window starts a search
+const AUTOCOMPLETE_DEBOUNCE: Duration = Duration::from_millis(400);
+/// Discord drops autocomplete responses after 3 seconds
+const AUTOCOMPLETE_DEADLINE: Duration = Duration::from_millis(2200);
+const SEARCH_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+const SEARCH_CACHE_SIZE: usize = 256;
+/// Discord's limit for choice names and values
+const MAX_CHOICE_LENGTH: usize = 100;
 
 fn is_playlist_url(url: &str) -> bool {
     url.contains("list=")
 }
 
+fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// One entry of a flat yt-dlp search
+#[derive(Debug, Deserialize, Clone)]
+pub struct SearchResult {
+    pub id: String,
+    pub title: String,
+    pub url: Option<String>,
+    pub duration: Option<f64>,
+    pub uploader: Option<String>,
+    pub channel: Option<String>,
+}
+
+impl SearchResult {
+    pub fn page_url(&self) -> String {
+        match &self.url {
+            Some(url) if is_url(url) => url.clone(),
+            _ => format!("https://www.youtube.com/watch?v={}", self.id),
+        }
+    }
+
+    pub fn artist(&self) -> Option<&str> {
+        self.uploader.as_deref().or(self.channel.as_deref())
+    }
+
+    /// `Title — Uploader (3:45)`
+    pub fn label(&self) -> String {
+        let mut label = self.title.clone();
+        if let Some(artist) = self.artist() {
+            label.push_str(&format!(" — {}", artist));
+        }
+        if let Some(duration) = self.duration {
+            label.push_str(&format!(" ({})", format_timestamp(duration)));
+        }
+        label
+    }
+}
+
+/// Top `limit` YouTube results for free-text `query`
+pub async fn search_youtube(query: &str, limit: usize) -> Result<Vec<SearchResult>> {
+    let output = Command::new("yt-dlp")
+        .arg("--flat-playlist")
+        .arg("-J")
+        .arg(format!("ytsearch{}:{}", limit, query))
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "yt-dlp search failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let json: Value = serde_json::from_slice(&output.stdout)?;
+    let results = json["entries"]
+        .as_array()
+        .map(|entries| {
+            entries
+                .iter()
+                .filter_map(|e| serde_json::from_value(e.clone()).ok())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(results)
+}
+
+/// Search results shared by autocomplete and `/play`, so picking a
+/// suggestion (or submitting the typed text) doesn't search twice
+pub struct SearchCache {
+    entries: Mutex<HashMap<String, (Instant, Vec<SearchResult>)>>,
+    /// Newest autocomplete request per user, for debouncing
+    latest: Mutex<HashMap<UserId, u64>>,
+    counter: AtomicU64,
+}
+
+impl SearchCache {
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            latest: Mutex::new(HashMap::new()),
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    fn key(query: &str) -> String {
+        query.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+    }
+
+    pub async fn cached(&self, query: &str) -> Option<Vec<SearchResult>> {
+        let entries = self.entries.lock().await;
+        entries
+            .get(&Self::key(query))
+            .filter(|(at, _)| at.elapsed() < SEARCH_CACHE_TTL)
+            .map(|(_, results)| results.clone())
+    }
+
+    pub async fn search(&self, query: &str) -> Result<Vec<SearchResult>> {
+        if let Some(results) = self.cached(query).await {
+            return Ok(results);
+        }
+
+        let results = search_youtube(query, SEARCH_RESULTS).await?;
+
+        let mut entries = self.entries.lock().await;
+        entries.retain(|_, (at, _)| at.elapsed() < SEARCH_CACHE_TTL);
+        if entries.len() >= SEARCH_CACHE_SIZE {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (at, _))| *at)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(Self::key(query), (Instant::now(), results.clone()));
+
+        Ok(results)
+    }
+
+    /// Registers a new keystroke and returns its ticket
+    async fn begin(&self, user_id: UserId) -> u64 {
+        let ticket = self.counter.fetch_add(1, Ordering::Relaxed);
+        self.latest.lock().await.insert(user_id, ticket);
+        ticket
+    }
+
+    async fn is_latest(&self, user_id: UserId, ticket: u64) -> bool {
+        self.latest.lock().await.get(&user_id) == Some(&ticket)
+    }
+}
+
+async fn search_cache(ctx: &Context) -> Arc<SearchCache> {
+    let data_read = ctx.data.read().await;
+    data_read
+        .get::<BotData>()
+        .expect("BotData missing")
+        .search_cache
+        .clone()
+}
+
+/// Turns `/play` input into something `fetch_tracks` understands:
+/// URLs pass through, anything else becomes the top search result.
+/// The error is a message meant for the user.
+pub async fn resolve_query(ctx: &Context, input: &str) -> Result<String, String> {
+    if is_url(input) {
+        return Ok(input.to_string());
+    }
+
+    let results = search_cache(ctx)
+        .await
+        .search(input)
+        .await
+        .map_err(|e| format!("Search failed: {}", e))?;
+
+    results
+        .first()
+        .map(|r| r.page_url())
+        .ok_or_else(|| format!("No results for **{}**", input))
+}
+
+/// Suggests search results for the `url` option of `/play` and `/playnext`
+pub async fn autocomplete(ctx: &Context, interaction: &CommandInteraction) {
+    let query = interaction
+        .data
+        .autocomplete()
+        .map(|o| o.value.trim().to_string())
+        .unwrap_or_default();
+
+    let mut response = CreateAutocompleteResponse::new();
+    for (name, value) in suggestions(ctx, interaction.user.id, &query).await {
+        response = response.add_string_choice(name, value);
+    }
+
+    if let Err(why) = interaction
+        .create_response(&ctx.http, CreateInteractionResponse::Autocomplete(response))
+        .await
+    {
+        println!("Cannot respond to autocomplete: {why}");
+    }
+}
+
+async fn suggestions(ctx: &Context, user_id: UserId, query: &str) -> Vec<(String, String)> {
+    let to_choices = |results: Vec<SearchResult>| {
+        results
+            .into_iter()
+            .map(|r| (r.label().chars().take(MAX_CHOICE_LENGTH).collect(), r.page_url()))
+            .filter(|(_, url): &(String, String)| url.len() <= MAX_CHOICE_LENGTH)
+            .collect::<Vec<_>>()
+    };
+
+    if is_url(query) {
+        if query.len() > MAX_CHOICE_LENGTH {
+            return Vec::new();
+        }
+        return vec![(query.to_string(), query.to_string())];
+    }
+    if query.chars().count() < MIN_QUERY_LENGTH {
+        return Vec::new();
+    }
+
+    let cache = search_cache(ctx).await;
+    if let Some(results) = cache.cached(query).await {
+        return to_choices(results);
+    }
+
+    let ticket = cache.begin(user_id).await;
+    tokio::time::sleep(AUTOCOMPLETE_DEBOUNCE).await;
+    if !cache.is_latest(user_id, ticket).await {
+        // The user kept typing; a newer request answers instead
+        return Vec::new();
+    }
+
+    // Spawned so a slow search still lands in the cache after the deadline
+    let search = {
+        let cache = cache.clone();
+        let query = query.to_string();
+        tokio::spawn(async move { cache.search(&query).await })
+    };
+    match timeout(AUTOCOMPLETE_DEADLINE, search).await {
+        Ok(Ok(Ok(results))) => to_choices(results),
+        Ok(Ok(Err(e))) => {
+            eprintln!("[SEARCH] Autocomplete search failed: {e}");
+            Vec::new()
+        }
+        _ => Vec::new(),
+    }
+}
+
 pub async fn fetch_youtube_metadata(video_url: &str) -> Result<Track> {
     let output = Command::new("yt-dlp")
         .arg("-j") // JSON output
         .arg("-f")
-        .arg("bestaudio[ext=m4a]/bestaudio/best")
+        .arg(AUDIO_FORMAT)
         .arg(video_url)
         .output()
         .await?;
@@ -31,67 +284,54 @@ pub async fn fetch_youtube_metadata(video_url: &str) -> Result<Track> {
         ));
     }
 
-    let mut data: Track = serde_json::from_slice(&output.stdout)?;
-
-    if data.url.is_none() {
-        let url_out = Command::new("yt-dlp")
-            .arg("-f")
-            .arg("bestaudio[ext=m4a]/bestaudio/best")
-            .arg("-g") // get direct media URL
-            .arg(video_url)
-            .output()
-            .await?;
-
-        if url_out.status.success() {
-            let stream_url = String::from_utf8_lossy(&url_out.stdout).trim().to_string();
-            data.url = Some(stream_url);
-        }
-    }
+    let data: Track = serde_json::from_slice(&output.stdout)?;
 
     Ok(data)
 }
 
-pub async fn run(
+/// Joins (or reuses) the voice channel the invoking user is in.
+/// The error is a message meant for the user.
+pub async fn join_caller_channel(
     ctx: &Context,
     command: &CommandInteraction,
-    _options: &[ResolvedOption<'_>],
-) -> String {
-    let mut guild_id: GuildId = Default::default();
-    let mut channel_id: ChannelId = Default::default();
+) -> Result<Arc<AudioPlayer>, String> {
+    join_user_channel(ctx, command.guild_id, command.user.id).await
+}
 
-    if let Some(g_id) = command.guild_id {
-        guild_id = g_id;
-        let user_id = command.user.id;
+/// Joins the voice channel `user_id` is in, for interactions other than
+/// slash commands (e.g. the `/search` menu)
+pub async fn join_user_channel(
+    ctx: &Context,
+    guild_id: Option<GuildId>,
+    user_id: UserId,
+) -> Result<Arc<AudioPlayer>, String> {
+    let guild_id: GuildId = match guild_id {
+        Some(g_id) => g_id,
+        None => return Err("This command only works in a guild".to_string()),
+    };
 
+    let channel_id: ChannelId = {
         let guild = match guild_id.to_guild_cached(&ctx.cache) {
             Some(g) => g,
             None => {
-                return "internal error 501".to_string();
+                return Err("internal error 501".to_string());
             }
         };
 
-        if let Some(voice_state) = guild.voice_states.get(&user_id) {
-            if let Some(ch_id) = voice_state.channel_id {
-                channel_id = ch_id;
-            } else {
-                return "You have to be in a voice channel to use this command".to_string();
+        match guild
+            .voice_states
+            .get(&user_id)
+            .and_then(|voice_state| voice_state.channel_id)
+        {
+            Some(ch_id) => ch_id,
+            None => {
+                return Err("You have to be in a voice channel to use this command".to_string());
             }
-        } else {
-            return "You have to be in a voice channel to use this command".to_string();
         }
-    }
+    };
 
     let token = std::env::var("DISCORD_TOKEN").expect("Error finding discord token");
 
-    let url_option = _options.first();
-    let url = match url_option {
-        Some(option) => match &option.value {
-            ResolvedValue::String(s) => s,
-            _ => "Failed to parse url",
-        },
-        None => "Failed to parse url",
-    };
-
     let data_read = ctx.data.read().await;
     let voice_api = data_read
         .get::<BotData>()
@@ -99,65 +339,101 @@ pub async fn run(
         .voice_api
         .clone();
 
-    let player = voice_api
+    voice_api
         .join(
             &token,
             guild_id.to_string().as_str(),
             channel_id.to_string().as_str(),
         )
         .await
-        .expect("Could not connect to voice");
+        .map_err(|e| format!("Could not connect to voice: {}", e))
+}
+
+pub async fn run(
+    ctx: &Context,
+    command: &CommandInteraction,
+    _options: &[ResolvedOption<'_>],
+) -> String {
+    let url_option = _options.first();
+    let input = match url_option {
+        Some(option) => match &option.value {
+            ResolvedValue::String(s) => s,
+            _ => "Failed to parse url",
+        },
+        None => "Failed to parse url",
+    };
+
+    let player = match join_caller_channel(ctx, command).await {
+        Ok(p) => p,
+        Err(msg) => return msg,
+    };
+    if let Some(guild_id) = command.guild_id {
+        announce_tracks(ctx, guild_id, command.channel_id, &player).await;
+    }
 
-    let result_msg: String;
+    let url = match resolve_query(ctx, input).await {
+        Ok(url) => url,
+        Err(msg) => return msg,
+    };
 
-    if is_playlist_url(url) {
-        match get_playlist_entries(url).await {
-            Ok(entries) => {
-                use futures::stream::{FuturesUnordered, StreamExt};
+    let mut tracks = match fetch_tracks(&url).await {
+        Ok(t) => t,
+        Err(msg) => return msg,
+    };
+    for track in &mut tracks {
+        track.set_requester(command.user.id.to_string());
+    }
 
-                let mut futures = FuturesUnordered::new();
+    let result_msg = match tracks.as_slice() {
+        [track] => format!("Added **{}** to queue", track.title),
+        _ => format!("Added {} tracks to queue", tracks.len()),
+    };
 
-                for (index, url) in entries.iter().enumerate() {
-                    futures.push(async move {
-                        let meta = fetch_youtube_metadata(&url).await;
-                        (index, meta)
-                    });
-                }
+    for track in tracks {
+        player.clone().enqueue(track).await;
+    }
 
-                let mut results: Vec<(usize, Result<Track, _>)> = Vec::new();
+    result_msg
+}
 
-                while let Some(r) = futures.next().await {
-                    results.push(r);
-                }
+/// Resolves a video or playlist URL into playable tracks, in playlist order.
+/// Playlist entries that fail to load are left out.
+/// The error is a message meant for the user.
+pub async fn fetch_tracks(url: &str) -> Result<Vec<Track>, String> {
+    if !is_playlist_url(url) {
+        return fetch_youtube_metadata(url)
+            .await
+            .map(|meta| vec![meta])
+            .map_err(|e| format!("Could not load track: {}", e));
+    }
 
-                results.sort_by_key(|(index, _)| *index);
+    let entries = get_playlist_entries(url)
+        .await
+        .map_err(|e| format!("Konnte Playlist nicht laden: {}", e))?;
 
-                let mut count = 0;
+    use futures::stream::{FuturesUnordered, StreamExt};
 
-                for (_, res) in results {
-                    if let Ok(meta) = res {
-                        let player_clone = player.clone();
-                        player_clone.enqueue(meta).await;
-                        count += 1;
-                    }
-                }
+    let mut futures = FuturesUnordered::new();
 
-                result_msg = format!("Added {} tracks to queue", count);
-            }
-            Err(e) => {
-                return format!("Konnte Playlist nicht laden: {}", e);
-            }
-        }
-    } else {
-        let meta = fetch_youtube_metadata(url).await.unwrap();
-        let title = meta.title.clone();
+    for (index, url) in entries.iter().enumerate() {
+        futures.push(async move {
+            let meta = fetch_youtube_metadata(&url).await;
+            (index, meta)
+        });
+    }
 
-        player.enqueue(meta).await;
+    let mut results: Vec<(usize, Result<Track, _>)> = Vec::new();
 
-        result_msg = format!("Added **{}** to queue", title);
+    while let Some(r) = futures.next().await {
+        results.push(r);
     }
 
-    result_msg
+    results.sort_by_key(|(index, _)| *index);
+
+    Ok(results
+        .into_iter()
+        .filter_map(|(_, res)| res.ok())
+        .collect())
 }
 
 async fn get_playlist_entries(url: &str) -> Result<Vec<String>, String> {
@@ -191,7 +467,8 @@ pub fn register() -> CreateCommand {
     CreateCommand::new("play")
         .description("Play a song from youtube")
         .add_option(
-            CreateCommandOption::new(CommandOptionType::String, "url", "Link to a youtube video")
+            CreateCommandOption::new(CommandOptionType::String, "url", "Link or search terms")
+                .set_autocomplete(true)
                 .required(true),
         )
 }
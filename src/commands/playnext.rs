@@ -0,0 +1,58 @@
+use crate::commands::nowplaying::announce_tracks;
+use crate::commands::play::{fetch_tracks, join_caller_channel, resolve_query};
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, ResolvedValue,
+};
+use serenity::builder::CreateCommand;
+use serenity::model::application::ResolvedOption;
+
+pub async fn run(
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> String {
+    let input = match options.first().map(|o| &o.value) {
+        Some(ResolvedValue::String(s)) => *s,
+        _ => return "Failed to parse url".to_string(),
+    };
+
+    let player = match join_caller_channel(ctx, command).await {
+        Ok(p) => p,
+        Err(msg) => return msg,
+    };
+    if let Some(guild_id) = command.guild_id {
+        announce_tracks(ctx, guild_id, command.channel_id, &player).await;
+    }
+
+    let url = match resolve_query(ctx, input).await {
+        Ok(url) => url,
+        Err(msg) => return msg,
+    };
+
+    let mut tracks = match fetch_tracks(&url).await {
+        Ok(t) => t,
+        Err(msg) => return msg,
+    };
+    for track in &mut tracks {
+        track.set_requester(command.user.id.to_string());
+    }
+
+    let result_msg = match tracks.as_slice() {
+        [track] => format!("Playing **{}** next", track.title),
+        _ => format!("Playing {} tracks next", tracks.len()),
+    };
+
+    player.enqueue_next(tracks).await;
+
+    result_msg
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("playnext")
+        .description("Play a song right after the current one")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "url", "Link or search terms")
+                .set_autocomplete(true)
+                .required(true),
+        )
+}
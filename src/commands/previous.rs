@@ -0,0 +1,60 @@
+use crate::BotData;
+use crate::discord_voice_api::voice::audio_commands::AudioCommand;
+use crate::discord_voice_api::voice::player::{AudioPlayer, Track};
+use serenity::all::{CommandInteraction, Context, CreateEmbed};
+use serenity::builder::CreateCommand;
+use std::sync::Arc;
+use tokio::sync::oneshot;
+
+/// Starts the most recently played track again, returns `None` if there is no history.
+/// Shared with the now-playing buttons.
+pub async fn go_back(player: &Arc<AudioPlayer>) -> Option<Track> {
+    // Only the producer knows whether the newest history entry is the
+    // current track (past the crossfade point) or the one before it
+    let (reply_tx, reply_rx) = oneshot::channel();
+    if player.send_to_producer(AudioCommand::Previous(reply_tx)).await {
+        // The reply is dropped unanswered if the producer stopped first
+        if let Ok(previous) = reply_rx.await {
+            return previous;
+        }
+    }
+
+    // Nothing playing, so the history entry can be queued directly
+    let track = player.get_queue().take_previous().await?;
+    player.clone().enqueue_next(vec![track.clone()]).await;
+    Some(track)
+}
+
+pub async fn run(ctx: &Context, command: &CommandInteraction) -> CreateEmbed {
+    let guild_id = match command.guild_id {
+        Some(g) => g.to_string(),
+        None => return CreateEmbed::new().title("❌ Not in a guild"),
+    };
+
+    let data_read = ctx.data.read().await;
+    let voice_api = data_read
+        .get::<BotData>()
+        .expect("BotData missing")
+        .voice_api
+        .clone();
+
+    let player = match voice_api.get_player(&guild_id).await {
+        Some(p) => p,
+        None => return CreateEmbed::new()
+            .title("❌ Not connected to voice")
+            .description("Use `/play` to connect the bot to a voice channel"),
+    };
+
+    let previous = match go_back(&player).await {
+        Some(track) => track,
+        None => return CreateEmbed::new().title("❌ No previous track"),
+    };
+
+    CreateEmbed::new()
+        .title("⏮ Playing previous track")
+        .description(previous.title)
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("previous").description("Go back to the previously played track")
+}
@@ -24,18 +24,26 @@ pub async fn run(ctx: &Context, command: &CommandInteraction) -> CreateEmbed {
     };
 
     let queue = player.get_queue();
+    let loop_mode = queue.loop_mode().await;
 
     if player.get_queue().is_empty().await {
         return CreateEmbed::new()
             .title("🎵 Queue is empty")
-            .description("Add songs with `/play`!");
+            .description("Add songs with `/play`!")
+            .field("Loop", loop_mode.label(), true);
     }
 
     let mut desc = String::new();
     for (i, track) in queue.iter().await.into_iter().take(20).enumerate() {
         let title = &track.title;
-        let id = track.id;
-        desc.push_str(&format!("**{}.** [{}](https://www.youtube.com/watch?v={})\n", i + 1, title, id));
+        desc.push_str(&format!("**{}.** [{}]({})", i + 1, title, track.page_url()));
+        if track.live() {
+            desc.push_str(" 🔴");
+        }
+        if let Some(user_id) = &track.requested_by {
+            desc.push_str(&format!(" · requested by <@{}>", user_id));
+        }
+        desc.push('\n');
     }
 
     let bot_avatar = bot_user.map(|b| b.bot_pfp_url.clone()).unwrap_or_default();
@@ -43,6 +51,7 @@ pub async fn run(ctx: &Context, command: &CommandInteraction) -> CreateEmbed {
     CreateEmbed::new()
         .title("🎶 Current queue")
         .description(desc)
+        .field("Loop", loop_mode.label(), true)
         .color(0xFF972C)
         .footer(
             CreateEmbedFooter::new(format!("Requested by {}", command.user.name))
@@ -0,0 +1,105 @@
+use crate::BotData;
+use crate::commands::play::join_caller_channel;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, CreateEmbed,
+    CreateEmbedAuthor,
+};
+use serenity::builder::CreateCommand;
+use serenity::model::application::ResolvedOption;
+use std::path::PathBuf;
+
+pub async fn run(
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> CreateEmbed {
+    let guild_id = match command.guild_id {
+        Some(g) => g.to_string(),
+        None => return CreateEmbed::new().title("❌ Not in a guild"),
+    };
+
+    match options.first().map(|o| o.name) {
+        Some("start") => {
+            let player = match join_caller_channel(ctx, command).await {
+                Ok(p) => p,
+                Err(msg) => {
+                    return CreateEmbed::new()
+                        .title("❌ Could not start recording")
+                        .description(msg);
+                }
+            };
+
+            let dir = PathBuf::from("recordings")
+                .join(&guild_id)
+                .join(chrono::Local::now().format("%Y-%m-%d_%H-%M-%S").to_string());
+
+            match player.start_recording(dir.clone()).await {
+                Ok(true) => CreateEmbed::new()
+                    .title("⏺ Recording started")
+                    .description(
+                        "Every speaker gets their own file, plus one mixed file.\nUse `/record stop` to finish.",
+                    )
+                    .author(CreateEmbedAuthor::new("Rusty MetalFistBot 7000"))
+                    .color(0xFF972C),
+                Ok(false) => CreateEmbed::new()
+                    .title("⏺ Already recording")
+                    .description("Use `/record stop` to finish the current recording"),
+                Err(e) => CreateEmbed::new()
+                    .title("❌ Could not start recording")
+                    .description(e.to_string()),
+            }
+        }
+        Some("stop") => {
+            let data_read = ctx.data.read().await;
+            let voice_api = data_read
+                .get::<BotData>()
+                .expect("BotData missing")
+                .voice_api
+                .clone();
+
+            let player = match voice_api.get_player(&guild_id).await {
+                Some(p) => p,
+                None => return CreateEmbed::new()
+                    .title("❌ Not connected to voice")
+                    .description("Use `/record start` to connect the bot to a voice channel"),
+            };
+
+            match player.stop_recording().await {
+                Ok(Some(files)) => {
+                    let desc = files
+                        .iter()
+                        .map(|f| format!("`{}`", f.display()))
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    CreateEmbed::new()
+                        .title(format!("⏹ Recording saved ({} files)", files.len()))
+                        .description(desc)
+                        .author(CreateEmbedAuthor::new("Rusty MetalFistBot 7000"))
+                        .color(0xFF972C)
+                }
+                Ok(None) => CreateEmbed::new()
+                    .title("❌ Not recording")
+                    .description("Use `/record start` to start a recording"),
+                Err(e) => CreateEmbed::new()
+                    .title("❌ Could not finish recording")
+                    .description(e.to_string()),
+            }
+        }
+        _ => CreateEmbed::new().title("❌ Unknown subcommand"),
+    }
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("record")
+        .description("Record the voice channel to Ogg/Opus files")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "start",
+            "Start recording every speaker",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "stop",
+            "Stop recording and save the files",
+        ))
+}
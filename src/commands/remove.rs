@@ -0,0 +1,63 @@
+use crate::BotData;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, CreateEmbed,
+    ResolvedValue,
+};
+use serenity::builder::CreateCommand;
+use serenity::model::application::ResolvedOption;
+
+pub async fn run(
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> CreateEmbed {
+    let position = match options.first().map(|o| &o.value) {
+        Some(ResolvedValue::Integer(p)) => *p,
+        _ => return CreateEmbed::new().title("❌ Missing queue position"),
+    };
+
+    let guild_id = match command.guild_id {
+        Some(g) => g.to_string(),
+        None => return CreateEmbed::new().title("❌ Not in a guild"),
+    };
+
+    let data_read = ctx.data.read().await;
+    let voice_api = data_read
+        .get::<BotData>()
+        .expect("BotData missing")
+        .voice_api
+        .clone();
+
+    let player = match voice_api.get_player(&guild_id).await {
+        Some(p) => p,
+        None => return CreateEmbed::new()
+            .title("❌ Not connected to voice")
+            .description("Use `/play` to connect the bot to a voice channel"),
+    };
+
+    let queue = player.get_queue();
+
+    let len = queue.len().await;
+    if position < 1 || position as usize > len {
+        return CreateEmbed::new()
+            .title("❌ Invalid queue position")
+            .description(format!("The queue has {} tracks", len));
+    }
+
+    match queue.remove(position as usize - 1).await {
+        Some(track) => CreateEmbed::new()
+            .title("🗑️ Removed from queue")
+            .description(format!("**{}.** {}", position, track.title)),
+        None => CreateEmbed::new().title("❌ Invalid queue position"),
+    }
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("remove")
+        .description("Remove a track from the queue")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Integer, "position", "Position in `/queue`")
+                .min_int_value(1)
+                .required(true),
+        )
+}
@@ -0,0 +1,202 @@
+use crate::commands::nowplaying::announce_tracks;
+use crate::commands::play::{fetch_youtube_metadata, join_user_channel, search_youtube};
+use crate::commands::seek::format_timestamp;
+use crate::{BotData, CommandResponse};
+use serenity::all::{
+    CommandInteraction, CommandOptionType, ComponentInteraction, ComponentInteractionDataKind,
+    Context, CreateActionRow, CreateCommandOption, CreateEmbed, CreateEmbedAuthor,
+    CreateEmbedFooter, CreateInteractionResponseFollowup, CreateSelectMenu, CreateSelectMenuKind,
+    CreateSelectMenuOption, EditInteractionResponse, ResolvedValue,
+};
+use serenity::builder::CreateCommand;
+use serenity::model::application::ResolvedOption;
+
+const SEARCH_LENGTH: usize = 10;
+pub const MENU_ID: &str = "search:select";
+/// Discord's limit for select option labels, descriptions and values
+const MAX_OPTION_LENGTH: usize = 100;
+
+fn truncate(text: &str) -> String {
+    if text.chars().count() <= MAX_OPTION_LENGTH {
+        return text.to_string();
+    }
+    let mut short: String = text.chars().take(MAX_OPTION_LENGTH - 1).collect();
+    short.push('…');
+    short
+}
+
+pub async fn run(
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> CommandResponse {
+    let query = match options.first().map(|o| &o.value) {
+        Some(ResolvedValue::String(s)) => s.trim(),
+        _ => return CommandResponse::Embed(CreateEmbed::new().title("❌ Missing search query")),
+    };
+
+    let results = match search_youtube(query, SEARCH_LENGTH).await {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("[SEARCH] Search for {:?} failed: {e}", query);
+            return CommandResponse::Embed(
+                CreateEmbed::new()
+                    .title("❌ Search failed")
+                    .description("yt-dlp could not search YouTube, try again later"),
+            );
+        }
+    };
+    // Select option values are capped, so results with oversized URLs can't be picked
+    let results: Vec<_> = results
+        .into_iter()
+        .filter(|r| r.page_url().len() <= MAX_OPTION_LENGTH)
+        .collect();
+
+    if results.is_empty() {
+        return CommandResponse::Embed(
+            CreateEmbed::new()
+                .title("🔎 No results")
+                .description(format!("Nothing found for **{}**", query)),
+        );
+    }
+
+    let data_read = ctx.data.read().await;
+    let bot_pfp_url = data_read
+        .get::<BotData>()
+        .map(|b| b.bot_pfp_url.clone())
+        .unwrap_or_default();
+
+    let mut desc = String::new();
+    let mut menu_options = Vec::new();
+    for (i, result) in results.iter().enumerate() {
+        let duration = result
+            .duration
+            .map(format_timestamp)
+            .unwrap_or_else(|| "?".to_string());
+        let artist = result.artist().unwrap_or("Unknown");
+
+        desc.push_str(&format!(
+            "**{}.** [{}]({}) — {} `{}`\n",
+            i + 1,
+            result.title,
+            result.page_url(),
+            artist,
+            duration
+        ));
+        menu_options.push(
+            CreateSelectMenuOption::new(
+                truncate(&format!("{}. {}", i + 1, result.title)),
+                result.page_url(),
+            )
+            .description(truncate(&format!("{} · {}", artist, duration))),
+        );
+    }
+
+    let menu = CreateSelectMenu::new(
+        MENU_ID,
+        CreateSelectMenuKind::String {
+            options: menu_options,
+        },
+    )
+    .placeholder("Pick tracks to add to the queue")
+    .min_values(1)
+    .max_values(results.len() as u8);
+
+    let embed = CreateEmbed::new()
+        .title(format!("🔎 Results for \"{}\"", query))
+        .description(desc)
+        .color(0xFF972C)
+        .footer(
+            CreateEmbedFooter::new(format!("Requested by {}", command.user.name))
+                .icon_url(bot_pfp_url),
+        )
+        .author(CreateEmbedAuthor::new("Rusty MetalFistBot 7000"));
+
+    CommandResponse::Components(embed, vec![CreateActionRow::SelectMenu(menu)])
+}
+
+/// Enqueues the tracks picked in a `/search` menu for whoever picked them
+pub async fn handle_select(ctx: &Context, component: &ComponentInteraction) {
+    let urls = match &component.data.kind {
+        ComponentInteractionDataKind::StringSelect { values } => values.clone(),
+        _ => return,
+    };
+
+    // Loading metadata takes longer than Discord's 3 second response window
+    if let Err(why) = component.defer(&ctx.http).await {
+        println!("Cannot defer search menu: {why}");
+        return;
+    }
+
+    match enqueue_selection(ctx, component, &urls).await {
+        Ok(msg) => {
+            let edit = EditInteractionResponse::new()
+                .content(msg)
+                .components(vec![]);
+            if let Err(why) = component.edit_response(&ctx.http, edit).await {
+                println!("Cannot update search results: {why}");
+            }
+        }
+        Err(msg) => {
+            let followup = CreateInteractionResponseFollowup::new()
+                .content(msg)
+                .ephemeral(true);
+            if let Err(why) = component.create_followup(&ctx.http, followup).await {
+                println!("Cannot respond to search menu: {why}");
+            }
+        }
+    }
+}
+
+async fn enqueue_selection(
+    ctx: &Context,
+    component: &ComponentInteraction,
+    urls: &[String],
+) -> Result<String, String> {
+    let player = join_user_channel(ctx, component.guild_id, component.user.id).await?;
+    if let Some(guild_id) = component.guild_id {
+        announce_tracks(ctx, guild_id, component.channel_id, &player).await;
+    }
+
+    let fetched =
+        futures::future::join_all(urls.iter().map(|url| fetch_youtube_metadata(url))).await;
+
+    let mut tracks = Vec::new();
+    for (url, result) in urls.iter().zip(fetched) {
+        match result {
+            Ok(mut track) => {
+                track.set_requester(component.user.id.to_string());
+                tracks.push(track);
+            }
+            Err(e) => eprintln!("[SEARCH] Could not load {}: {e}", url),
+        }
+    }
+
+    let result_msg = match tracks.as_slice() {
+        [] => return Err("Could not load any of the selected tracks".to_string()),
+        [track] => format!(
+            "<@{}> added **{}** to queue",
+            component.user.id, track.title
+        ),
+        _ => format!(
+            "<@{}> added {} tracks to queue",
+            component.user.id,
+            tracks.len()
+        ),
+    };
+
+    for track in tracks {
+        player.clone().enqueue(track).await;
+    }
+
+    Ok(result_msg)
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("search")
+        .description("Search YouTube and pick tracks to add to the queue")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "query", "What to search for")
+                .required(true),
+        )
+}
@@ -0,0 +1,141 @@
+use crate::BotData;
+use crate::discord_voice_api::voice::audio_commands::AudioCommand;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, CreateEmbed,
+    ResolvedValue,
+};
+use serenity::builder::CreateCommand;
+use serenity::model::application::ResolvedOption;
+use std::time::Duration;
+
+/// Where to seek, relative to the current position or absolute
+pub enum SeekTarget {
+    Absolute(f64),
+    Relative(f64),
+}
+
+/// Longest position a timestamp may name
+const MAX_TIMESTAMP_SECS: f64 = 24.0 * 3600.0;
+
+/// Parses `83`, `1:23` or `1:02:03` into seconds
+pub fn parse_timestamp(input: &str) -> Option<f64> {
+    let parts: Vec<&str> = input.trim().split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+
+    let mut seconds = 0.0;
+    for part in parts {
+        let part = part.trim();
+        // f64 parsing would also take `inf`, `NaN` and `1e30`
+        if part.is_empty() || !part.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            return None;
+        }
+        let value: f64 = part.parse().ok()?;
+        seconds = seconds * 60.0 + value;
+    }
+    (seconds <= MAX_TIMESTAMP_SECS).then_some(seconds)
+}
+
+/// Formats seconds as `m:ss`, or `h:mm:ss` for anything an hour or longer
+pub fn format_timestamp(seconds: f64) -> String {
+    let total = seconds.max(0.0) as u64;
+    let (h, m, s) = (total / 3600, (total % 3600) / 60, total % 60);
+    if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{}:{:02}", m, s)
+    }
+}
+
+/// Shared by `/seek`, `/forward` and `/rewind`
+pub async fn seek(ctx: &Context, command: &CommandInteraction, target: SeekTarget) -> CreateEmbed {
+    let guild_id = match command.guild_id {
+        Some(g) => g.to_string(),
+        None => return CreateEmbed::new().title("❌ Not in a guild"),
+    };
+
+    let data_read = ctx.data.read().await;
+    let voice_api = data_read
+        .get::<BotData>()
+        .expect("BotData missing")
+        .voice_api
+        .clone();
+
+    let player = match voice_api.get_player(&guild_id).await {
+        Some(p) => p,
+        None => return CreateEmbed::new()
+            .title("❌ Not connected to voice")
+            .description("Use `/play` to connect the bot to a voice channel"),
+    };
+
+    let track = match player.get_queue().get_current_track().await {
+        Some(t) => t,
+        None => return CreateEmbed::new()
+            .title("🎵 No track currently playing")
+            .description("Add songs with `/play`!"),
+    };
+
+    if track.live() {
+        return CreateEmbed::new()
+            .title("❌ Can't seek in a live stream")
+            .description(track.title);
+    }
+
+    let current = player.playback_position.read().await.seconds;
+    let mut new_position = match target {
+        SeekTarget::Absolute(s) => s,
+        SeekTarget::Relative(delta) => current + delta,
+    }
+    .max(0.0);
+    if let Some(total) = track.known_duration() {
+        new_position = new_position.min(total);
+    }
+    let Ok(position) = Duration::try_from_secs_f64(new_position) else {
+        return CreateEmbed::new()
+            .title("❌ Invalid position")
+            .description(track.title);
+    };
+
+    player
+        .playback_cmd_tx
+        .send(AudioCommand::Seek(position))
+        .await
+        .expect("Playback channel invalid");
+
+    let position_text = match track.known_duration() {
+        Some(total) => format!("{} / {}", format_timestamp(new_position), format_timestamp(total)),
+        None => format_timestamp(new_position),
+    };
+
+    CreateEmbed::new()
+        .title(format!("⏩ Seeked to **{}**", position_text))
+        .description(track.title)
+}
+
+pub async fn run(
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> CreateEmbed {
+    let seconds = match options.first().map(|o| &o.value) {
+        Some(ResolvedValue::String(s)) => match parse_timestamp(s) {
+            Some(secs) => secs,
+            None => return CreateEmbed::new()
+                .title("❌ Invalid position")
+                .description("Use a timestamp like `1:23` or `83`"),
+        },
+        _ => return CreateEmbed::new().title("❌ Missing position"),
+    };
+
+    seek(ctx, command, SeekTarget::Absolute(seconds)).await
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("seek")
+        .description("Jump to a position in the current track")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "position", "Timestamp, e.g. 1:23")
+                .required(true),
+        )
+}
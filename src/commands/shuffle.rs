@@ -0,0 +1,41 @@
+use crate::BotData;
+use serenity::all::{CommandInteraction, Context, CreateEmbed};
+use serenity::builder::CreateCommand;
+
+pub async fn run(ctx: &Context, command: &CommandInteraction) -> CreateEmbed {
+    let guild_id = match command.guild_id {
+        Some(g) => g.to_string(),
+        None => return CreateEmbed::new().title("❌ Not in a guild"),
+    };
+
+    let data_read = ctx.data.read().await;
+    let voice_api = data_read
+        .get::<BotData>()
+        .expect("BotData missing")
+        .voice_api
+        .clone();
+
+    let player = match voice_api.get_player(&guild_id).await {
+        Some(p) => p,
+        None => return CreateEmbed::new()
+            .title("❌ Not connected to voice")
+            .description("Use `/play` to connect the bot to a voice channel"),
+    };
+
+    let queue = player.get_queue();
+
+    if queue.is_empty().await {
+        return CreateEmbed::new()
+            .title("🎵 Queue is empty")
+            .description("Add songs with `/play`!");
+    }
+
+    queue.shuffle().await;
+    CreateEmbed::new()
+        .title("🔀 Queue shuffled")
+        .description(format!("{} tracks", queue.len().await))
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("shuffle").description("Shuffle the upcoming tracks")
+}
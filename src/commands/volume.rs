@@ -0,0 +1,57 @@
+use crate::BotData;
+use crate::discord_voice_api::voice::audio_commands::AudioCommand;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, CreateEmbed,
+    ResolvedValue,
+};
+use serenity::builder::CreateCommand;
+use serenity::model::application::ResolvedOption;
+
+pub async fn run(
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> CreateEmbed {
+    let guild_id = match command.guild_id {
+        Some(g) => g.to_string(),
+        None => return CreateEmbed::new().title("❌ Not in a guild"),
+    };
+
+    let level = match options.first().map(|o| &o.value) {
+        Some(ResolvedValue::Integer(l)) => (*l).clamp(0, 200),
+        _ => return CreateEmbed::new().title("❌ Missing volume level"),
+    };
+
+    let data_read = ctx.data.read().await;
+    let voice_api = data_read
+        .get::<BotData>()
+        .expect("BotData missing")
+        .voice_api
+        .clone();
+
+    let player = match voice_api.get_player(&guild_id).await {
+        Some(p) => p,
+        None => return CreateEmbed::new()
+            .title("❌ Not connected to voice")
+            .description("Use `/play` to connect the bot to a voice channel"),
+    };
+
+    player
+        .filter_cmd_tx
+        .send(AudioCommand::SetVolume(level as f32 / 100.0))
+        .await
+        .expect("Filter channel invalid");
+
+    CreateEmbed::new().title(format!("🔊 Volume set to **{}%**", level))
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("volume")
+        .description("Set the playback volume")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Integer, "level", "Volume in percent (0-200)")
+                .min_int_value(0)
+                .max_int_value(200)
+                .required(true),
+        )
+}
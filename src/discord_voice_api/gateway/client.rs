@@ -1,11 +1,14 @@
+use crate::discord_voice_api::heartbeat::HeartbeatTracker;
 use anyhow::Result;
 use futures_util::{SinkExt, StreamExt};
 use serde_json::Value;
 use socket2::{Domain, Socket, Type};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::{
     net::TcpStream,
-    sync::{Mutex, mpsc},
+    sync::{Mutex, Notify, mpsc, watch},
+    task::JoinHandle,
     time::Duration,
 };
 use tokio_tungstenite::{
@@ -19,70 +22,110 @@ pub struct Gateway {
     >,
     ws_rx:
         Arc<Mutex<futures_util::stream::SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>>>,
-    events_tx: mpsc::Sender<Value>,
     heartbeat_interval: Arc<Mutex<Option<u64>>>,
+    heartbeat_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    session_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    heartbeat: Arc<HeartbeatTracker>,
+    zombie: Arc<Notify>,
     session_id: Arc<Mutex<Option<String>>>,
     resume_url: Arc<Mutex<Option<String>>>,
     last_seq: Arc<Mutex<Option<i64>>>,
+    user_id: Arc<watch::Sender<Option<String>>>,
+    voice_routes: Arc<Mutex<HashMap<String, mpsc::Sender<Value>>>>, // key = guild_id
 }
 
 impl Gateway {
-    pub async fn connect() -> Result<(Self, mpsc::Receiver<Value>)> {
+    pub async fn connect() -> Result<Self> {
         let url = Url::parse("wss://gateway.discord.gg/?v=10&encoding=json")?;
         let (ws, _) = connect_async(url).await?;
         let (ws_tx, ws_rx) = ws.split();
 
         let ws_tx = Arc::new(Mutex::new(ws_tx));
         let ws_rx = Arc::new(Mutex::new(ws_rx));
-        let (events_tx, events_rx) = mpsc::channel(100);
+        let (user_id, _) = watch::channel(None);
 
         let gw = Self {
             ws_tx,
             ws_rx,
-            events_tx,
             heartbeat_interval: Arc::new(Mutex::new(None)),
+            heartbeat_task: Arc::new(Mutex::new(None)),
+            session_task: Arc::new(Mutex::new(None)),
+            heartbeat: Arc::new(HeartbeatTracker::new()),
+            zombie: Arc::new(Notify::new()),
             session_id: Arc::new(Mutex::new(None)),
             resume_url: Arc::new(Mutex::new(None)),
             last_seq: Arc::new(Mutex::new(None)),
+            user_id: Arc::new(user_id),
+            voice_routes: Arc::new(Mutex::new(HashMap::new())),
         };
 
-        Ok((gw, events_rx))
+        Ok(gw)
     }
 
     fn clone_for_task(&self) -> Self {
         Self {
             ws_tx: self.ws_tx.clone(),
             ws_rx: self.ws_rx.clone(),
-            events_tx: self.events_tx.clone(),
             heartbeat_interval: self.heartbeat_interval.clone(),
+            heartbeat_task: self.heartbeat_task.clone(),
+            session_task: self.session_task.clone(),
+            heartbeat: self.heartbeat.clone(),
+            zombie: self.zombie.clone(),
             session_id: self.session_id.clone(),
             resume_url: self.resume_url.clone(),
             last_seq: self.last_seq.clone(),
+            user_id: self.user_id.clone(),
+            voice_routes: self.voice_routes.clone(),
         }
     }
 
-    pub async fn start(&mut self, token: &str) -> Result<()> {
+    /// Identifies once and keeps the session alive in a background task.
+    /// The socket opened by `connect` is reused for the first cycle.
+    pub async fn start(&self, token: &str) -> Result<()> {
         let token = token.to_string();
+        let mut this = self.clone_for_task();
+
+        this.identify(&token).await?;
 
-            self.gateway_loop(&*token).await;
+        let handle = tokio::spawn(async move {
+            if let Err(err) = this.listen_loop().await {
+                eprintln!("⚠️ Error: {err:?}");
+            }
+            this.gateway_loop(&token).await;
+        });
+        *self.session_task.lock().await = Some(handle);
 
         Ok(())
     }
 
+    /// Stops the session task started by `start` and closes the socket,
+    /// so a gateway that never became ready doesn't keep identifying.
+    pub async fn shutdown(&self) {
+        if let Some(task) = self.session_task.lock().await.take() {
+            task.abort();
+        }
+        if let Some(hb) = self.heartbeat_task.lock().await.take() {
+            hb.abort();
+        }
+        let mut ws = self.ws_tx.lock().await;
+        let _ = ws.send(Message::Close(None)).await;
+        let _ = ws.close().await;
+        println!("🔌 Gateway shut down");
+    }
+
     pub async fn gateway_loop(&mut self, token: &str) {
         loop {
+            println!("⏳ Reconnecting in 5s…");
+            tokio::time::sleep(Duration::from_secs(5)).await;
             println!("🔌 Starting gateway cycle…");
 
-            match self.run_gateway_cycle(token).await {
-                Ok(()) => {
-                    println!("Gateway exited cleanly.");
-                    return; // beendet Hintergrundtask
-                }
-                Err(err) => {
-                    eprintln!("⚠️ Error: {err:?}");
-                    println!("⏳ Reconnecting in 5s…");
-                    tokio::time::sleep(Duration::from_secs(5)).await;
-                }
+            if let Err(err) = self.run_gateway_cycle(token).await {
+                eprintln!("⚠️ Error: {err:?}");
+                continue;
+            }
+
+            if let Err(err) = self.listen_loop().await {
+                eprintln!("⚠️ Error: {err:?}");
             }
         }
     }
@@ -107,11 +150,6 @@ impl Gateway {
             self.reconnect_ws(false).await?;
         }
 
-        let mut this = self.clone_for_task();
-        tokio::spawn(async move {
-            this.listen_loop().await.expect("Listen Loop exited");
-        });
-
         if trying_resume {
             match self.send_resume(token).await {
                 Ok(()) => println!("🔁 Sent RESUME"),
@@ -140,18 +178,28 @@ impl Gateway {
 
         println!("🌐 Connecting to {}", url);
 
+        if let Some(old) = self.heartbeat_task.lock().await.take() {
+            old.abort();
+        }
+
         let (ws, _) = connect_async(url).await?;
         let (tx, rx) = ws.split();
 
-        self.ws_tx = Arc::new(Mutex::new(tx));
-        self.ws_rx = Arc::new(Mutex::new(rx));
+        // Swap the streams in place so every clone of this gateway follows the new socket
+        *self.ws_tx.lock().await = tx;
+        *self.ws_rx.lock().await = rx;
 
         Ok(())
     }
 
     pub async fn listen_loop(&mut self) -> Result<()> {
         loop {
-            let msg = self.ws_rx.lock().await.next().await;
+            let msg = tokio::select! {
+                msg = async { self.ws_rx.lock().await.next().await } => msg,
+                _ = self.zombie.notified() => {
+                    return Err(anyhow::anyhow!("Zombie connection (no HEARTBEAT_ACK)"));
+                }
+            };
 
             let msg = match msg {
                 Some(Ok(m)) => m,
@@ -169,10 +217,19 @@ impl Gateway {
                         10 => {
                             if let Some(interval) = json["d"]["heartbeat_interval"].as_u64() {
                                 *self.heartbeat_interval.lock().await = Some(interval);
-                                Self::spawn_heartbeat(self.ws_tx.clone(), interval);
+                                self.heartbeat.reset();
+                                let handle = self.spawn_heartbeat(interval);
+                                if let Some(old) = self.heartbeat_task.lock().await.replace(handle) {
+                                    old.abort();
+                                }
                             }
                         }
 
+                        // HEARTBEAT_ACK
+                        11 => {
+                            self.heartbeat.acked();
+                        }
+
                         // DISPATCH
                         0 => {
                             if let Some(s) = json["s"].as_i64() {
@@ -186,6 +243,22 @@ impl Gateway {
                                         json["d"]["resume_gateway_url"]
                                             .as_str()
                                             .map(|s| s.to_string());
+                                    if let Some(user_id) = json["d"]["user"]["id"].as_str() {
+                                        println!("✅ READY — Bot ID: {}", user_id);
+                                        self.user_id.send_replace(Some(user_id.to_string()));
+                                    }
+                                }
+
+                                "VOICE_STATE_UPDATE" => {
+                                    // Only our own voice state carries the session_id we need
+                                    let own_id = self.user_id.borrow().clone();
+                                    if json["d"]["user_id"].as_str() == own_id.as_deref() {
+                                        self.route_voice_event(&json).await;
+                                    }
+                                }
+
+                                "VOICE_SERVER_UPDATE" => {
+                                    self.route_voice_event(&json).await;
                                 }
 
                                 "RESUMED" => {
@@ -217,8 +290,6 @@ impl Gateway {
 
                         _ => {}
                     }
-
-                    let _ = self.events_tx.send(json).await;
                 }
 
                 Message::Close(c) => {
@@ -275,37 +346,76 @@ impl Gateway {
         Ok(())
     }
 
-    fn spawn_heartbeat(
-        ws_tx: Arc<
-            Mutex<
-                futures_util::stream::SplitSink<
-                    WebSocketStream<MaybeTlsStream<TcpStream>>,
-                    Message,
-                >,
-            >,
-        >,
-        interval_ms: u64,
-    ) {
+    fn spawn_heartbeat(&self, interval_ms: u64) -> JoinHandle<()> {
+        let ws_tx = self.ws_tx.clone();
+        let last_seq = self.last_seq.clone();
+        let heartbeat = self.heartbeat.clone();
+        let zombie = self.zombie.clone();
+
         tokio::spawn(async move {
             let mut delay = tokio::time::interval(Duration::from_millis(interval_ms));
             loop {
                 delay.tick().await;
-                let heartbeat = serde_json::json!({ "op": 1, "d": null });
+                if !heartbeat.sent() {
+                    // Keep nudging until the listen loop picks it up and reconnects
+                    println!("🧟 No HEARTBEAT_ACK since last heartbeat — reconnecting");
+                    zombie.notify_waiters();
+                    continue;
+                }
+
+                let seq = *last_seq.lock().await;
+                let payload = serde_json::json!({ "op": 1, "d": seq });
                 let mut ws = ws_tx.lock().await;
-                if ws.send(Message::Text(heartbeat.to_string())).await.is_ok() {
+                if ws.send(Message::Text(payload.to_string())).await.is_ok() {
                     //  println!("❤️ Sent Heartbeat");
                 } else {
                     println!("⚠️ Heartbeat failed");
                     break;
                 }
             }
-        });
+        })
+    }
+
+    /// Round-trip time of the last acknowledged heartbeat
+    pub fn latency(&self) -> Option<Duration> {
+        self.heartbeat.latency()
+    }
+
+    async fn route_voice_event(&self, event: &Value) {
+        let Some(guild_id) = event["d"]["guild_id"].as_str() else {
+            return;
+        };
+
+        let mut routes = self.voice_routes.lock().await;
+        if let Some(tx) = routes.get(guild_id) {
+            if tx.try_send(event.clone()).is_err() {
+                println!("⚠️ Dropping voice event for guild {}", guild_id);
+                if tx.is_closed() {
+                    routes.remove(guild_id);
+                }
+            }
+        }
+    }
+
+    /// Registers a per-guild receiver for VOICE_STATE_UPDATE / VOICE_SERVER_UPDATE.
+    /// Replaces any previous route for the same guild.
+    pub async fn subscribe_voice(&self, guild_id: &str) -> mpsc::Receiver<Value> {
+        let (tx, rx) = mpsc::channel(16);
+        self.voice_routes
+            .lock()
+            .await
+            .insert(guild_id.to_string(), tx);
+        rx
+    }
+
+    pub async fn unsubscribe_voice(&self, guild_id: &str) {
+        self.voice_routes.lock().await.remove(guild_id);
     }
 
     pub async fn wait_for_voice_info(
-        &mut self,
+        &self,
         guild_id: &str,
-        mut events_rx: mpsc::Receiver<Value>
+        events_rx: &mut mpsc::Receiver<Value>,
     ) -> Result<(String, String, String)> {
         let mut session_id = None;
         let mut token = None;
@@ -339,27 +449,16 @@ impl Gateway {
         ))
     }
 
-    pub async fn wait_until_ready(&mut self,         mut events_rx: mpsc::Receiver<Value>
-    ) -> (Option<(mpsc::Receiver<Value>, String)>) {
-        println!("⏳ Waiting for READY / GUILD_CREATE...");
-        while let Some(event) = events_rx.recv().await {
-            if let Some(t) = event["t"].as_str() {
-                match t {
-                    "READY" => {
-                        if let Some(user_id) = event["d"]["user"]["id"].as_str() {
-                            println!("✅ READY — Bot ID: {}", user_id);
-                            return Some((events_rx, user_id.to_string()));
-                        }
-                    }
-                    "GUILD_CREATE" => {
-                        println!("✅ GUILD_CREATE — initial guild loaded");
-                    }
-                    _ => {}
-                }
+    pub async fn wait_until_ready(&self) -> Option<String> {
+        println!("⏳ Waiting for READY...");
+        let mut user_id = self.user_id.subscribe();
+        match user_id.wait_for(|id| id.is_some()).await {
+            Ok(id) => id.clone(),
+            Err(_) => {
+                println!("⚠️ No READY event received before gateway closed");
+                None
             }
         }
-        println!("⚠️ No READY event received before channel closed");
-        None
     }
 
     pub async fn send_json(&self, payload: &serde_json::Value) -> Result<()> {
@@ -0,0 +1,53 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Tracks heartbeats and their ACKs for one websocket.
+/// Shared between the heartbeat task (sends) and the reader task (ACKs).
+#[derive(Default)]
+pub struct HeartbeatTracker {
+    state: Mutex<HeartbeatState>,
+}
+
+#[derive(Default)]
+struct HeartbeatState {
+    last_sent: Option<Instant>,
+    awaiting_ack: bool,
+    latency: Option<Duration>,
+}
+
+impl HeartbeatTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a heartbeat about to be sent.
+    /// Returns `false` if the previous one was never acknowledged (zombie connection).
+    pub fn sent(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.awaiting_ack {
+            return false;
+        }
+        state.last_sent = Some(Instant::now());
+        state.awaiting_ack = true;
+        true
+    }
+
+    pub fn acked(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(sent) = state.last_sent {
+            state.latency = Some(sent.elapsed());
+        }
+        state.awaiting_ack = false;
+    }
+
+    /// Called on a fresh HELLO; keeps the last measured latency
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.last_sent = None;
+        state.awaiting_ack = false;
+    }
+
+    pub fn latency(&self) -> Option<Duration> {
+        self.state.lock().unwrap().latency
+    }
+}
@@ -1,29 +1,60 @@
 use crate::discord_voice_api::gateway::Gateway;
 use crate::discord_voice_api::voice::VoiceConnection;
+use crate::discord_voice_api::voice::connection::{VoiceServerInfo, VoiceSession};
 use crate::discord_voice_api::voice::player::AudioPlayer;
 use anyhow::Result;
-use futures_util::SinkExt;
-use serde_json::json;
+use serde_json::{Value, json};
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio_tungstenite::tungstenite::Message;
+use tokio::sync::{Mutex, mpsc};
+use tokio::time::{Duration, timeout};
 
 pub mod gateway;
+pub mod heartbeat;
 pub mod udp;
 pub mod voice;
 
+const VOICE_INFO_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct DiscordVoiceApi {
     connections: Arc<Mutex<HashMap<String, Arc<AudioPlayer>>>>, // key = guild_id
+    gateway: Mutex<Option<(Arc<Gateway>, String)>>,             // (gateway, bot user_id)
 }
 
 impl DiscordVoiceApi {
     pub fn new() -> Self {
         Self {
             connections: Arc::new(Mutex::new(HashMap::new())),
+            gateway: Mutex::new(None),
         }
     }
 
+    /// Returns the shared gateway, identifying on first use.
+    async fn gateway(&self, token: &str) -> Result<(Arc<Gateway>, String)> {
+        let mut gw_lock = self.gateway.lock().await;
+        if let Some((gateway, user_id)) = gw_lock.as_ref() {
+            return Ok((gateway.clone(), user_id.clone()));
+        }
+
+        let gateway = Gateway::connect().await?;
+        gateway.start(token).await?;
+
+        let ready = timeout(VOICE_INFO_TIMEOUT, gateway.wait_until_ready())
+            .await
+            .ok()
+            .flatten();
+        let Some(user_id) = ready else {
+            // Otherwise the session task keeps running and the next join identifies again
+            gateway.shutdown().await;
+            return Err(anyhow::anyhow!("No READY event received"));
+        };
+
+        let gateway = Arc::new(gateway);
+        *gw_lock = Some((gateway.clone(), user_id.clone()));
+
+        Ok((gateway, user_id))
+    }
+
     pub async fn get_player(&self, guild_id: &str) -> Option<Arc<AudioPlayer>> {
         let conns = self.connections.lock().await;
 
@@ -40,17 +71,8 @@ impl DiscordVoiceApi {
             return Ok(player);
         }
 
-        let mut res = Gateway::connect().await?;
-        
-        let mut gateway = res.0;
-        let event_rx = res.1;
-
-        gateway.start(token).await?;
-
-        let (event_rx, user_id) = gateway
-            .wait_until_ready(event_rx)
-            .await
-            .ok_or_else(|| anyhow::anyhow!("No READY event received"))?;
+        let (gateway, user_id) = self.gateway(token).await?;
+        let mut voice_rx = gateway.subscribe_voice(guild_id).await;
 
         let join_payload = serde_json::json!({
             "op": 4,
@@ -61,39 +83,137 @@ impl DiscordVoiceApi {
                 "self_deaf": false
             }
         });
-        gateway.send_json(&join_payload).await?;
+        if let Err(e) = gateway.send_json(&join_payload).await {
+            gateway.unsubscribe_voice(guild_id).await;
+            return Err(e);
+        }
         println!("🎤 Sent Voice State Update (JOIN)");
 
-        let (session_id, voice_token, endpoint) = gateway.wait_for_voice_info(guild_id, event_rx).await?;
-        println!("✅ Got Voice Info — endpoint: {}", endpoint);
+        let connected = async {
+            let (session_id, voice_token, endpoint) = timeout(
+                VOICE_INFO_TIMEOUT,
+                gateway.wait_for_voice_info(guild_id, &mut voice_rx),
+            )
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out waiting for voice info"))??;
+            println!("✅ Got Voice Info — endpoint: {}", endpoint);
+
+            let (voice_conn, voice_session) = VoiceConnection::connect(VoiceServerInfo {
+                endpoint,
+                token: voice_token,
+                session_id,
+                guild_id: guild_id.to_string(),
+                user_id,
+            })
+            .await?;
+
+            // Bot-Speaking ON
+            let ssrc = voice_conn.read().await.ssrc;
+            voice_session.set_speaking(ssrc, 5).await?;
+            Ok::<_, anyhow::Error>((voice_conn, voice_session))
+        }
+        .await;
+        let (voice_conn, voice_session) = match connected {
+            Ok(c) => c,
+            Err(e) => {
+                gateway.unsubscribe_voice(guild_id).await;
+                return Err(e);
+            }
+        };
 
-        let voice_conn =
-            VoiceConnection::connect(endpoint, voice_token, session_id, guild_id, user_id).await?;
+        let player = AudioPlayer::new(voice_conn, voice_session.clone());
+        self.connections
+            .lock()
+            .await
+            .insert(guild_id.to_string(), player.clone());
+
+        let connections = self.connections.clone();
+        let guild_id = guild_id.to_string();
+        let watched = player.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                // The route stays registered while connected, Discord moves calls
+                // between voice servers with another VOICE_SERVER_UPDATE
+                _ = follow_server_moves(voice_rx, &voice_session) => {}
+                _ = voice_session.wait_closed() => {
+                    println!("⚠️ Voice connection for guild {} lost, leaving", guild_id);
+                    let removed = {
+                        let mut conns = connections.lock().await;
+                        // Unless the guild was left and joined again in the meantime
+                        match conns.get(&guild_id) {
+                            Some(p) if Arc::ptr_eq(p, &watched) => conns.remove(&guild_id),
+                            _ => None,
+                        }
+                    };
+                    if let Some(player) = removed
+                        && let Err(e) = teardown(&player, Some(&gateway), &guild_id).await
+                    {
+                        eprintln!("⚠️ Could not leave guild {}: {e:?}", guild_id);
+                    }
+                }
+            }
+        });
 
-        let player = AudioPlayer::new(voice_conn.0.clone(), voice_conn.1.clone());
+        Ok(player)
+    }
 
-        // Bot-Speaking ON
-        let speaking_payload = json!({
-            "op": 5,
-            "d": { "speaking": 5, "delay": 0, "ssrc": voice_conn.0.ssrc }
-        });
-        {
-            let mut w = voice_conn.1.ws.lock().await;
-            w.send(Message::Text(speaking_payload.to_string())).await?;
-        }
+    /// Heartbeat round trip of the gateway connection used for voice joins
+    pub async fn gateway_latency(&self) -> Option<Duration> {
+        let gw_lock = self.gateway.lock().await;
+        gw_lock.as_ref().and_then(|(gateway, _)| gateway.latency())
+    }
 
-        let mut conns = self.connections.lock().await;
-        conns.insert(guild_id.to_string(), player.clone());
+    /// Returns `false` if the bot was not connected in this guild.
+    pub async fn leave(&self, guild_id: &str) -> Result<bool> {
+        let player = self.connections.lock().await.remove(guild_id);
+        let Some(player) = player else {
+            return Ok(false);
+        };
 
-        Ok(player)
+        let gateway = self.gateway.lock().await.as_ref().map(|(gw, _)| gw.clone());
+        teardown(&player, gateway.as_deref(), guild_id).await?;
+        Ok(true)
+    }
+}
+
+/// Stops the player, closes its voice connection and leaves the channel.
+/// The player must already be removed from `connections`.
+async fn teardown(player: &AudioPlayer, gateway: Option<&Gateway>, guild_id: &str) -> Result<()> {
+    if let Err(e) = player.disconnect().await {
+        eprintln!("⚠️ Voice teardown failed for guild {}: {e:?}", guild_id);
+    }
+
+    if let Some(gateway) = gateway {
+        gateway.unsubscribe_voice(guild_id).await;
+        let leave_payload = json!({
+            "op": 4,
+            "d": {
+                "guild_id": guild_id,
+                "channel_id": null,
+                "self_mute": false,
+                "self_deaf": false
+            }
+        });
+        gateway.send_json(&leave_payload).await?;
     }
 
-    /* pub async fn leave(&self, guild_id: &str) -> Result<()> {
-        let mut conns = self.connections.lock().await;
-        if let Some(player) = conns.remove(guild_id) {
-            player.conn.disconnect().await?; // Annahme: VoiceConnection hat eine disconnect-Methode
-            println!("🛑 Left voice channel for guild {}", guild_id);
+    println!("🛑 Left voice channel for guild {}", guild_id);
+    Ok(())
+}
+
+/// Applies VOICE_SERVER_UPDATEs to a live connection until the guild's
+/// voice route is dropped (leave or rejoin)
+async fn follow_server_moves(mut voice_rx: mpsc::Receiver<Value>, session: &VoiceSession) {
+    while let Some(event) = voice_rx.recv().await {
+        if event["t"].as_str() != Some("VOICE_SERVER_UPDATE") {
+            continue;
         }
-        Ok(())
-    }*/
+        // A null endpoint means the server is gone and a new one isn't allocated yet
+        let endpoint = event["d"]["endpoint"].as_str().map(str::to_string);
+        let token = event["d"]["token"].as_str().map(str::to_string);
+        if let (Some(endpoint), Some(token)) = (endpoint, token) {
+            println!("🔀 Voice server moved — endpoint: {}", endpoint);
+            session.move_server(endpoint, token).await;
+        }
+    }
 }
@@ -35,7 +35,7 @@ pub async fn wait_for_ready(
     ws_rx: &TokioMutex<
         futures_util::stream::SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
     >,
-) -> Result<(u32, String, u16)> {
+) -> Result<(u32, String, u16, Vec<String>)> {
     loop {
         let msg_opt = {
             let mut rx = ws_rx.lock().await;
@@ -49,7 +49,16 @@ pub async fn wait_for_ready(
                 let ssrc = data["d"]["ssrc"].as_u64().unwrap() as u32;
                 let ip = data["d"]["ip"].as_str().unwrap().to_string();
                 let port = data["d"]["port"].as_u64().unwrap() as u16;
-                return Ok((ssrc, ip, port));
+                let modes = data["d"]["modes"]
+                    .as_array()
+                    .map(|modes| {
+                        modes
+                            .iter()
+                            .filter_map(|m| m.as_str().map(|s| s.to_string()))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                return Ok((ssrc, ip, port, modes));
             }
         }
     }
@@ -82,3 +91,24 @@ pub async fn wait_for_secret(
         }
     }
 }
+
+pub async fn wait_for_resumed(
+    ws_rx: &TokioMutex<
+        futures_util::stream::SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+    >,
+) -> Result<()> {
+    loop {
+        let msg_opt = {
+            let mut rx = ws_rx.lock().await;
+            rx.next().await
+        };
+
+        let msg = msg_opt.ok_or_else(|| anyhow::anyhow!("Voice WebSocket closed"))??;
+        if let Message::Text(txt) = msg {
+            let data: Value = serde_json::from_str(&txt)?;
+            if data["op"] == 9 {
+                return Ok(());
+            }
+        }
+    }
+}
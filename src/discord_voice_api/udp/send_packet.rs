@@ -2,6 +2,9 @@ use std::sync::atomic::Ordering;
 use crate::discord_voice_api::voice::VoiceConnection;
 use opus::Encoder;
 
+/// Opus "silence" frame, sent five times before going quiet to avoid interpolation artifacts
+pub const OPUS_SILENCE_FRAME: [u8; 3] = [0xF8, 0xFF, 0xFE];
+
 pub async fn send_voice_packet(
     conn: &VoiceConnection,
     pcm_samples: &[i16],
@@ -14,6 +17,15 @@ pub async fn send_voice_packet(
     let n = encoder.encode(pcm_samples, &mut opus_buf)?;
     let opus_payload = &opus_buf[..n];
 
+    send_opus_packet(conn, opus_payload, seq, timestamp).await
+}
+
+pub async fn send_opus_packet(
+    conn: &VoiceConnection,
+    opus_payload: &[u8],
+    seq: u16,
+    timestamp: u32,
+) -> anyhow::Result<()> {
     // RTP header
     let mut rtp_header = [0u8; 12];
     rtp_header[0] = 0x80;
@@ -1,8 +1,10 @@
 use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{Mutex, RwLock, oneshot};
+use tokio::time::Duration;
 use augmented_dsp_filters::rbj::{FilterProcessor, FilterType};
 use audio_processor_traits::{AudioBuffer, AudioProcessor, AudioProcessorSettings, AudioContext};
 use audio_processor_traits::simple_processor::MultiChannel;
+use crate::discord_voice_api::voice::player::Track;
 
 #[derive(Clone, Debug)]
 pub struct AudioFilterState {
@@ -31,7 +33,13 @@ pub enum AudioCommand {
 
     Pause,
     Resume,
-    Skip
+    Skip,
+    Stop,
+    /// Restart decoding of the current track at the given position
+    Seek(Duration),
+    /// Replay the most recent history entry, then continue with the current track.
+    /// Answers with the track it went back to, `None` without history.
+    Previous(oneshot::Sender<Option<Track>>),
 }
 
 pub type SharedAudioFilterState = Arc<RwLock<AudioFilterState>>;
@@ -162,4 +170,59 @@ impl Compressor {
     }
 }
 
+/// Applies the volume with a per-sample ramp towards the target gain,
+/// so level changes don't click. Boosts above 100% go through a soft limiter.
+pub struct VolumeControl {
+    gain: f32,
+}
+
+impl VolumeControl {
+    /// Largest gain change per 20 ms frame (full 0 → 200% sweep in ~0.4 s)
+    const MAX_STEP: f32 = 0.1;
+    /// Limiter knee, as fraction of full scale
+    const KNEE: f32 = 0.8;
+
+    pub fn new(gain: f32) -> Self {
+        Self { gain }
+    }
+
+    /// `true` once any ramp has settled at 100%, so `apply` leaves frames untouched
+    pub fn is_unity(&self) -> bool {
+        self.gain == 1.0
+    }
+
+    pub fn apply(&mut self, frame: &mut [i16], channels: usize, target: f32) {
+        let start = self.gain;
+        let end = start + (target - start).clamp(-Self::MAX_STEP, Self::MAX_STEP);
+        self.gain = end;
+
+        if start == 1.0 && end == 1.0 {
+            return;
+        }
+
+        let num_samples = (frame.len() / channels).max(1);
+        for (n, chunk) in frame.chunks_mut(channels).enumerate() {
+            let gain = start + (end - start) * (n as f32 / num_samples as f32);
+            for s in chunk.iter_mut() {
+                let mut x = *s as f32 / 32768.0 * gain;
+                if gain > 1.0 {
+                    x = Self::soft_limit(x);
+                }
+                *s = (x * 32768.0).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            }
+        }
+    }
+
+    /// Linear below the knee, tanh curve above it, never exceeds full scale
+    fn soft_limit(x: f32) -> f32 {
+        let a = x.abs();
+        if a <= Self::KNEE {
+            return x;
+        }
+        let range = 1.0 - Self::KNEE;
+        let limited = Self::KNEE + range * ((a - Self::KNEE) / range).tanh();
+        limited.copysign(x)
+    }
+}
+
 pub type SharedAudioFilters = Arc<Mutex<AudioFilters>>;
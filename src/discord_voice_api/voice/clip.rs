@@ -0,0 +1,101 @@
+use crate::discord_voice_api::voice::ogg_writer::{OggOpusWriter, SAMPLES_PER_FRAME};
+use crate::discord_voice_api::voice::receiver::{ReceivedAudio, RtpClock};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+pub const CLIP_SECONDS: u64 = 30;
+const FRAMES_PER_SECOND: u64 = 50;
+const FRAME_SAMPLES: usize = (SAMPLES_PER_FRAME * 2) as usize;
+
+/// Rolling window of everything audible in the channel: incoming voice
+/// plus the bot's own outgoing frames, mixed on a shared 20 ms clock.
+pub struct ClipBuffer {
+    started: Instant,
+    slots: Vec<Vec<i16>>,
+    stamps: Vec<Option<u64>>, // frame index currently held by each slot
+    speakers: HashMap<u32, RtpClock>, // key = ssrc
+}
+
+pub type SharedClipBuffer = Arc<Mutex<ClipBuffer>>;
+
+impl ClipBuffer {
+    pub fn new(seconds: u64) -> Self {
+        let capacity = (seconds * FRAMES_PER_SECOND) as usize;
+        Self {
+            started: Instant::now(),
+            slots: vec![vec![0; FRAME_SAMPLES]; capacity],
+            stamps: vec![None; capacity],
+            speakers: HashMap::new(),
+        }
+    }
+
+    fn current_frame(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64 / 20
+    }
+
+    /// Mixes one of the bot's own 20 ms frames into the slot for "now".
+    /// The consumer paces these, so the arrival time is exact.
+    pub fn push(&mut self, pcm: &[i16]) {
+        let frame = self.current_frame();
+        self.mix_into(frame, pcm);
+    }
+
+    /// Mixes an incoming voice frame into the slot of its RTP timestamp.
+    /// Arrival times jitter, so consecutive frames could land in one slot.
+    pub fn push_voice(&mut self, audio: &ReceivedAudio) {
+        let now = self.current_frame();
+        let clock = self
+            .speakers
+            .entry(audio.ssrc)
+            .or_insert_with(|| RtpClock::new(audio.timestamp, now));
+
+        // `None` for a late packet from just before the speaker's first one
+        let Some(frame) = clock.place(audio.timestamp, now) else {
+            return;
+        };
+        self.mix_into(frame, &audio.pcm);
+    }
+
+    fn mix_into(&mut self, frame: u64, pcm: &[i16]) {
+        let idx = (frame % self.slots.len() as u64) as usize;
+
+        if self.stamps[idx] != Some(frame) {
+            self.slots[idx].fill(0);
+            self.stamps[idx] = Some(frame);
+        }
+
+        for (acc, &s) in self.slots[idx].iter_mut().zip(pcm.iter()) {
+            *acc = acc.saturating_add(s);
+        }
+    }
+
+    /// The last `seconds` of audio, oldest frame first, gaps filled with silence
+    pub fn snapshot(&self, seconds: u64) -> Vec<Vec<i16>> {
+        let now = self.current_frame();
+        let wanted = (seconds * FRAMES_PER_SECOND).min(self.slots.len() as u64);
+        let first = now.saturating_sub(wanted - 1);
+
+        (first..=now)
+            .map(|frame| {
+                let idx = (frame % self.slots.len() as u64) as usize;
+                if self.stamps[idx] == Some(frame) {
+                    self.slots[idx].clone()
+                } else {
+                    vec![0; FRAME_SAMPLES]
+                }
+            })
+            .collect()
+    }
+}
+
+/// Encodes frames from `ClipBuffer::snapshot` into an in-memory Ogg/Opus file
+pub fn encode_clip(frames: &[Vec<i16>]) -> Result<Vec<u8>> {
+    let mut writer = OggOpusWriter::new(Vec::new())?;
+    for frame in frames {
+        writer.write_frame(frame)?;
+    }
+    writer.finish()
+}
@@ -1,18 +1,26 @@
 use anyhow::Result;
 use futures_util::{SinkExt, StreamExt};
-use serde_json::json;
-use std::sync::{Arc, Mutex};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::sync::atomic::AtomicU32;
 use std::time::Duration;
 use tokio::net::TcpStream;
 use tokio::net::UdpSocket;
+use tokio::sync::{Mutex as TokioMutex, Notify, RwLock};
+use tokio::task::JoinHandle;
 use tokio::time::sleep;
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async, tungstenite::Message};
 use url::Url;
-use crate::discord_voice_api::gateway::Gateway;
+use crate::discord_voice_api::heartbeat::HeartbeatTracker;
 use crate::discord_voice_api::udp::{handshake, setup};
-use crate::discord_voice_api::voice::crypto::CipherMode;
-use crate::discord_voice_api::voice::player::AudioPlayer;
+use crate::discord_voice_api::voice::crypto::{self, CipherMode};
+use crate::discord_voice_api::voice::receiver::SpeakerMap;
+
+type WsSink = futures_util::stream::SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
+type WsStream = futures_util::stream::SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
+
+const MAX_RECONNECT_ATTEMPTS: u32 = 5;
 
 #[derive(Clone)]
 pub struct VoiceConnection {
@@ -23,44 +31,114 @@ pub struct VoiceConnection {
     pub counter: Arc<AtomicU32>,
 }
 
+/// Swapped in place when the voice connection has to be re-established
+pub type SharedVoiceConnection = Arc<RwLock<VoiceConnection>>;
+
+/// Everything needed to (re-)identify or resume on the voice gateway
+#[derive(Clone, Debug)]
+pub struct VoiceServerInfo {
+    pub endpoint: String,
+    pub token: String,
+    pub session_id: String,
+    pub guild_id: String,
+    pub user_id: String,
+}
+
 #[derive(Clone)]
 pub struct VoiceSession {
-    pub ws: Arc<
-        tokio::sync::Mutex<
-            futures_util::stream::SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
-        >,
-    >,
+    pub ws: Arc<TokioMutex<WsSink>>,
+    ws_rx: Arc<TokioMutex<WsStream>>,
+    /// Endpoint and token change when Discord moves the call to another server
+    info: Arc<RwLock<VoiceServerInfo>>,
+    heartbeat_task: Arc<TokioMutex<Option<JoinHandle<()>>>>,
+    reader_task: Arc<TokioMutex<Option<JoinHandle<()>>>>,
+    heartbeat: Arc<HeartbeatTracker>,
+    zombie: Arc<Notify>,
+    server_moved: Arc<Notify>,
+    /// Signalled when the reader gives up on the connection for good
+    closed: Arc<Notify>,
+    speakers: SpeakerMap,
+}
+
+#[derive(Debug, PartialEq)]
+enum CloseAction {
+    Resume,
+    Reconnect,
+    Stop,
+}
+
+/// Maps a voice gateway close code to what we should do about it.
+/// `None` means the socket dropped without a close frame.
+fn close_action(code: Option<u16>) -> CloseAction {
+    match code {
+        // Session no longer valid / session timeout: resuming will be rejected
+        Some(4006) | Some(4009) => CloseAction::Reconnect,
+        // Auth failed, disconnected (kicked / channel deleted), server not found,
+        // unknown protocol, unknown encryption mode, bad request, rate limited,
+        // call terminated
+        Some(4004) | Some(4011) | Some(4012) | Some(4014) | Some(4016) | Some(4020)
+        | Some(4021) | Some(4022) => CloseAction::Stop,
+        // Voice server crashed, network errors and everything else
+        _ => CloseAction::Resume,
+    }
 }
 
 unsafe impl Send for VoiceConnection {}
 unsafe impl Sync for VoiceConnection {}
 
 impl VoiceConnection {
-    pub async fn connect(
-        endpoint: String,
-        token: String,
-        session_id: String,
-        guild_id: &str,
-        user_id: String,
-    ) -> Result<(Self, VoiceSession)> {
-        let url = format!("wss://{}", endpoint);
-        let (ws, _) = connect_async(Url::parse(&url)?).await?;
-        let (tx, rx) = ws.split();
-        let ws_tx = Arc::new(tokio::sync::Mutex::new(tx));
-        let ws_rx = Arc::new(tokio::sync::Mutex::new(rx));
+    pub async fn connect(info: VoiceServerInfo) -> Result<(SharedVoiceConnection, VoiceSession)> {
+        let (ws_tx, ws_rx) = open_ws(&info.endpoint).await?;
+
+        let session = VoiceSession {
+            ws: Arc::new(TokioMutex::new(ws_tx)),
+            ws_rx: Arc::new(TokioMutex::new(ws_rx)),
+            info: Arc::new(RwLock::new(info)),
+            heartbeat_task: Arc::new(TokioMutex::new(None)),
+            reader_task: Arc::new(TokioMutex::new(None)),
+            heartbeat: Arc::new(HeartbeatTracker::new()),
+            zombie: Arc::new(Notify::new()),
+            server_moved: Arc::new(Notify::new()),
+            closed: Arc::new(Notify::new()),
+            speakers: Arc::new(RwLock::new(HashMap::new())),
+        };
+
+        let conn = Arc::new(RwLock::new(session.identify().await?));
 
+        let reader = {
+            let session = session.clone();
+            let conn = conn.clone();
+            tokio::spawn(async move { session.read_loop(conn).await })
+        };
+        *session.reader_task.lock().await = Some(reader);
+
+        Ok((conn, session))
+    }
+}
+
+async fn open_ws(endpoint: &str) -> Result<(WsSink, WsStream)> {
+    let url = format!("wss://{}", endpoint);
+    let (ws, _) = connect_async(Url::parse(&url)?).await?;
+    Ok(ws.split())
+}
+
+impl VoiceSession {
+    /// Full IDENTIFY → READY → SELECT_PROTOCOL flow on the current websocket.
+    /// Returns a fresh UDP socket and cipher.
+    async fn identify(&self) -> Result<VoiceConnection> {
         // Identify (Voice)
         {
+            let info = self.info.read().await.clone();
             let identify = json!({
                 "op": 0,
                 "d": {
-                    "server_id": guild_id,
-                    "user_id": user_id,
-                    "session_id": session_id,
-                    "token": token
+                    "server_id": info.guild_id,
+                    "user_id": info.user_id,
+                    "session_id": info.session_id,
+                    "token": info.token
                 }
             });
-            ws_tx
+            self.ws
                 .lock()
                 .await
                 .send(Message::Text(identify.to_string()))
@@ -69,51 +147,27 @@ impl VoiceConnection {
         println!("🎧 Sent Voice Identify");
 
         // Wait for HELLO
-        let heartbeat_interval = handshake::wait_for_hello(&ws_rx).await?;
+        let heartbeat_interval = handshake::wait_for_hello(&self.ws_rx).await?;
         println!("📡 Voice heartbeat interval: {}ms", heartbeat_interval);
+        self.spawn_heartbeat(heartbeat_interval).await;
 
-        // Spawn heartbeat loop
-        {
-            let hb_tx = ws_tx.clone();
-            tokio::spawn(async move {
-                let interval = Duration::from_millis(heartbeat_interval);
-                loop {
-                    sleep(interval).await;
-                    let heartbeat = json!({ "op": 3, "d": chrono::Utc::now().timestamp_millis() });
-                    if let Err(e) = hb_tx
-                        .lock()
-                        .await
-                        .send(Message::Text(heartbeat.to_string()))
-                        .await
-                    {
-                        eprintln!("Fehler beim Senden des Voice Heartbeats: {:?}", e);
-                        break;
-                    }
-                    //  println!("❤️ Voice Heartbeat gesendet");
-                }
-            });
-        }
-
-        // READY (ssrc, ip, port)
-        let (ssrc, server_ip, server_port) = handshake::wait_for_ready(&ws_rx).await?;
+        // READY (ssrc, ip, port, modes)
+        let (ssrc, server_ip, server_port, modes) = handshake::wait_for_ready(&self.ws_rx).await?;
         println!(
             "✅ Voice Ready received! {}:{} (ssrc={})",
             server_ip, server_port, ssrc
         );
 
+        let selected_mode = crypto::select_mode(&modes)?;
+        println!("🔐 Selected encryption mode: {}", selected_mode);
+
         // UDP socket
-        /*let udp_socket = Arc::new({
-            let s = UdpSocket::bind("0.0.0.0:0").await?;
-            s.connect((server_ip.as_str(), server_port)).await?;
-            s
-        });*/
         let udp_socket = Arc::new({
             let s = setup::make_udp_socket("0.0.0.0:0").await?;
             s.connect((server_ip.as_str(), server_port)).await?;
             s
         });
 
-
         // IP discovery
         let (address, port) = setup::discover_ip(ssrc, &udp_socket).await?;
         println!("🌍 Discovered external IP: {}:{}", address, port);
@@ -127,56 +181,293 @@ impl VoiceConnection {
                     "data": {
                         "address": address,
                         "port": port,
-                        "mode": "aead_xchacha20_poly1305_rtpsize"
+                        "mode": selected_mode
                     }
                 }
             });
 
-            let mut w = ws_tx.lock().await;
+            let mut w = self.ws.lock().await;
             w.send(Message::Text(select_protocol.to_string())).await?;
         }
 
         // SECRET KEY
-        let (secret_key, mode) = handshake::wait_for_secret(&ws_rx).await?;
+        let (secret_key, mode) = handshake::wait_for_secret(&self.ws_rx).await?;
         let cipher = CipherMode::from_secret_and_mode(&secret_key, &mode)?;
         println!("🔑 Received secret key, mode: {}", mode);
 
-        {
-            let ws_rx_clone = ws_rx.clone();
-            tokio::spawn(async move {
-                loop {
-                    let msg_opt = {
-                        let mut lock = ws_rx_clone.lock().await;
-                        lock.next().await
+        Ok(VoiceConnection {
+            socket: udp_socket,
+            ssrc,
+            mode,
+            cipher,
+            counter: Arc::new(AtomicU32::new(0)),
+        })
+    }
+
+    /// Sends op 7 RESUME on the current websocket. UDP socket and cipher stay valid.
+    async fn resume(&self) -> Result<()> {
+        let info = self.info.read().await.clone();
+        let resume = json!({
+            "op": 7,
+            "d": {
+                "server_id": info.guild_id,
+                "session_id": info.session_id,
+                "token": info.token
+            }
+        });
+        self.ws
+            .lock()
+            .await
+            .send(Message::Text(resume.to_string()))
+            .await?;
+        println!("🔁 Sent Voice Resume");
+
+        let heartbeat_interval = handshake::wait_for_hello(&self.ws_rx).await?;
+        self.spawn_heartbeat(heartbeat_interval).await;
+        handshake::wait_for_resumed(&self.ws_rx).await?;
+        println!("🔁 Voice session resumed");
+
+        Ok(())
+    }
+
+    /// Replaces the websocket streams in place so every clone follows the new socket
+    async fn reopen_ws(&self) -> Result<()> {
+        let endpoint = self.info.read().await.endpoint.clone();
+        let (tx, rx) = open_ws(&endpoint).await?;
+        *self.ws.lock().await = tx;
+        *self.ws_rx.lock().await = rx;
+        Ok(())
+    }
+
+    async fn spawn_heartbeat(&self, heartbeat_interval: u64) {
+        let hb_tx = self.ws.clone();
+        let tracker = self.heartbeat.clone();
+        let zombie = self.zombie.clone();
+        tracker.reset();
+
+        let handle = tokio::spawn(async move {
+            let interval = Duration::from_millis(heartbeat_interval);
+            loop {
+                sleep(interval).await;
+                if !tracker.sent() {
+                    // Keep nudging until the reader picks it up and resumes
+                    println!("🧟 No voice HEARTBEAT_ACK since last heartbeat — resuming");
+                    zombie.notify_waiters();
+                    continue;
+                }
+
+                let heartbeat = json!({ "op": 3, "d": chrono::Utc::now().timestamp_millis() });
+                if let Err(e) = hb_tx
+                    .lock()
+                    .await
+                    .send(Message::Text(heartbeat.to_string()))
+                    .await
+                {
+                    eprintln!("Fehler beim Senden des Voice Heartbeats: {:?}", e);
+                    break;
+                }
+                //  println!("❤️ Voice Heartbeat gesendet");
+            }
+        });
+
+        if let Some(old) = self.heartbeat_task.lock().await.replace(handle) {
+            old.abort();
+        }
+    }
+
+    /// Reads the voice websocket until it closes, then resumes or reconnects
+    /// depending on the close code.
+    async fn read_loop(&self, conn: SharedVoiceConnection) {
+        loop {
+            let mut action = tokio::select! {
+                code = self.read_until_closed() => {
+                    let action = close_action(code);
+                    println!("🔌 Voice WebSocket closed (code {:?}) → {:?}", code, action);
+                    action
+                }
+                _ = self.server_moved.notified() => {
+                    println!("🔀 Voice server moved → Reconnect");
+                    CloseAction::Reconnect
+                }
+            };
+
+            if action == CloseAction::Stop {
+                eprintln!("Voice WebSocket wurde geschlossen");
+                self.shut_down().await;
+                return;
+            }
+
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                if attempt > MAX_RECONNECT_ATTEMPTS {
+                    eprintln!("❌ Giving up on voice reconnect after {} attempts", MAX_RECONNECT_ATTEMPTS);
+                    self.shut_down().await;
+                    return;
+                }
+
+                match self.reestablish(&action, &conn).await {
+                    Ok(()) => break,
+                    Err(e) => {
+                        eprintln!("⚠️ Voice {:?} failed: {e:?}", action);
+                        // A failed resume falls back to a full identify
+                        action = CloseAction::Reconnect;
+                        sleep(Duration::from_secs(attempt as u64)).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The connection is dead; whoever owns it has to tear it down
+    async fn shut_down(&self) {
+        if let Some(hb) = self.heartbeat_task.lock().await.take() {
+            hb.abort();
+        }
+        self.closed.notify_one();
+    }
+
+    /// Resolves once the reader gave up on the connection (kicked, channel
+    /// deleted, auth failed or too many failed reconnects)
+    pub async fn wait_closed(&self) {
+        self.closed.notified().await;
+    }
+
+    async fn reestablish(&self, action: &CloseAction, conn: &SharedVoiceConnection) -> Result<()> {
+        self.reopen_ws().await?;
+
+        match action {
+            CloseAction::Resume => self.resume().await,
+            _ => {
+                let new_conn = self.identify().await?;
+                let ssrc = new_conn.ssrc;
+                *conn.write().await = new_conn;
+                self.set_speaking(ssrc, 5).await?;
+                println!("✅ Voice connection re-established (ssrc={})", ssrc);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the close code, or `None` if the socket dropped without one
+    async fn read_until_closed(&self) -> Option<u16> {
+        loop {
+            let msg_opt = tokio::select! {
+                msg = async { self.ws_rx.lock().await.next().await } => msg,
+                _ = self.zombie.notified() => return None,
+            };
+            match msg_opt {
+                Some(Ok(Message::Text(txt))) => {
+                    //  println!("📨 WS-Text: {}", txt);
+                    let Ok(data) = serde_json::from_str::<Value>(&txt) else {
+                        continue;
                     };
-                    match msg_opt {
-                        Some(Ok(Message::Text(txt))) => {
-                            //  println!("📨 WS-Text: {}", txt);
+                    match data["op"].as_i64() {
+                        // HEARTBEAT_ACK
+                        Some(6) => self.heartbeat.acked(),
+                        // SPEAKING
+                        Some(5) => {
+                            let ssrc = data["d"]["ssrc"].as_u64();
+                            let user_id = data["d"]["user_id"].as_str();
+                            if let (Some(ssrc), Some(user_id)) = (ssrc, user_id) {
+                                self.speakers
+                                    .write()
+                                    .await
+                                    .insert(ssrc as u32, user_id.to_string());
+                            }
                         }
-                        Some(Ok(_)) => continue,
-                        Some(Err(e)) => {
-                            eprintln!("Fehler beim Lesen des Voice WebSockets: {:?}", e);
-                            break;
-                        }
-                        None => {
-                            eprintln!("Voice WebSocket wurde geschlossen");
-                            break;
+                        // CLIENT_DISCONNECT
+                        Some(13) => {
+                            if let Some(user_id) = data["d"]["user_id"].as_str() {
+                                self.speakers.write().await.retain(|_, u| u != user_id);
+                            }
                         }
+                        _ => {}
                     }
                 }
-            });
+                Some(Ok(Message::Close(frame))) => {
+                    return frame.map(|f| u16::from(f.code));
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    eprintln!("Fehler beim Lesen des Voice WebSockets: {:?}", e);
+                    return None;
+                }
+                None => return None,
+            }
         }
+    }
 
-        let conn = VoiceConnection {
-            socket: udp_socket,
-            ssrc,
-            mode: mode.clone(),
-            cipher,
-            counter: Arc::new(AtomicU32::new(0)),
-        };
+    /// Handles a VOICE_SERVER_UPDATE for a live connection: the old server
+    /// is going away, so the reader identifies on the new one.
+    pub async fn move_server(&self, endpoint: String, token: String) {
+        {
+            let mut info = self.info.write().await;
+            info.endpoint = endpoint;
+            info.token = token;
+        }
+        self.server_moved.notify_one();
+    }
 
-        let session = VoiceSession { ws: ws_tx };
+    pub fn speakers(&self) -> SpeakerMap {
+        self.speakers.clone()
+    }
 
-        Ok((conn, session))
+    /// Round-trip time of the last acknowledged voice heartbeat
+    pub fn latency(&self) -> Option<Duration> {
+        self.heartbeat.latency()
+    }
+
+    pub async fn set_speaking(&self, ssrc: u32, speaking: u8) -> Result<()> {
+        let speaking_payload = json!({
+            "op": 5,
+            "d": { "speaking": speaking, "delay": 0, "ssrc": ssrc }
+        });
+        let mut w = self.ws.lock().await;
+        w.send(Message::Text(speaking_payload.to_string())).await?;
+        Ok(())
+    }
+
+    /// Stops the heartbeat and reader tasks and closes the voice websocket.
+    pub async fn close(&self) -> Result<()> {
+        if let Some(reader) = self.reader_task.lock().await.take() {
+            reader.abort();
+        }
+        if let Some(hb) = self.heartbeat_task.lock().await.take() {
+            hb.abort();
+        }
+
+        let mut w = self.ws.lock().await;
+        w.send(Message::Close(None)).await?;
+        w.close().await?;
+        println!("🔌 Voice WebSocket closed");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn close_codes_map_to_actions() {
+        let cases = [
+            (None, CloseAction::Resume),
+            (Some(1000), CloseAction::Resume),
+            (Some(4015), CloseAction::Resume),
+            (Some(4006), CloseAction::Reconnect),
+            (Some(4009), CloseAction::Reconnect),
+            (Some(4004), CloseAction::Stop),
+            (Some(4011), CloseAction::Stop),
+            (Some(4012), CloseAction::Stop),
+            (Some(4014), CloseAction::Stop),
+            (Some(4016), CloseAction::Stop),
+            (Some(4020), CloseAction::Stop),
+            (Some(4021), CloseAction::Stop),
+            (Some(4022), CloseAction::Stop),
+        ];
+        for (code, action) in cases {
+            assert_eq!(close_action(code), action, "close code {code:?}");
+        }
     }
 }
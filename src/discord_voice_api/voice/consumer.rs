@@ -1,9 +1,10 @@
-use crate::discord_voice_api::udp::send_packet::send_voice_packet;
-use crate::discord_voice_api::voice::connection::VoiceConnection;
+use crate::discord_voice_api::udp::send_packet::{send_opus_packet, send_voice_packet};
+use crate::discord_voice_api::voice::clip::SharedClipBuffer;
+use crate::discord_voice_api::voice::connection::SharedVoiceConnection;
 use crate::discord_voice_api::voice::audio_commands::{
-    AudioCommand, SharedAudioFilterState, SharedAudioFilters,
+    AudioCommand, SharedAudioFilterState, SharedAudioFilters, VolumeControl,
 };
-use crate::discord_voice_api::voice::player::AudioFrame;
+use crate::discord_voice_api::voice::player::PlaybackFrame;
 use anyhow::Result;
 use opus::{Application, Channels, Encoder};
 use std::sync::Arc;
@@ -12,13 +13,14 @@ use tokio::sync::mpsc;
 use tokio::time::{Duration, MissedTickBehavior};
 
 pub async fn audio_consumer(
-    conn: Arc<VoiceConnection>,
+    conn: SharedVoiceConnection,
     seq: Arc<AtomicU16>,
     ts: Arc<AtomicU32>,
-    mut rx: mpsc::Receiver<AudioFrame>,
+    mut rx: mpsc::Receiver<PlaybackFrame>,
     mut cmd_rx: mpsc::Receiver<AudioCommand>,
     filter_state: SharedAudioFilterState,
     filters: SharedAudioFilters,
+    clip_buffer: SharedClipBuffer,
 ) -> Result<mpsc::Receiver<AudioCommand>, anyhow::Error> {
     let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio)?;
     let mut tick = tokio::time::interval(Duration::from_millis(20));
@@ -26,10 +28,12 @@ pub async fn audio_consumer(
 
     let mut seq_val = seq.load(Ordering::Relaxed);
     let mut ts_val = ts.load(Ordering::Relaxed);
+    let mut volume = VolumeControl::new(filter_state.read().await.volume);
+    let mut passthrough = false;
 
     println!("[CONSUMER] Ready to send audio");
 
-    while let Some(mut frame) = rx.recv().await {
+    while let Some(PlaybackFrame { pcm: mut frame, opus }) = rx.recv().await {
         tick.tick().await;
 
         while let Ok(cmd) = cmd_rx.try_recv() {
@@ -41,10 +45,16 @@ pub async fn audio_consumer(
                 }
                 AudioCommand::ToggleNightcore(on) => {
                     state.nightcore = on;
+                    if on {
+                        state.vaporwave = false;
+                    }
                     println!("[FILTER] Nightcore = {}", on);
                 }
                 AudioCommand::ToggleVaporwave(on) => {
                     state.vaporwave = on;
+                    if on {
+                        state.nightcore = false;
+                    }
                     println!("[FILTER] Vaporwave = {}", on);
                 }
                 AudioCommand::SetVolume(vol) => {
@@ -55,17 +65,40 @@ pub async fn audio_consumer(
             }
         }
 
-        if filter_state.read().await.bass_boost {
+        let (bass_boost, target_volume) = {
+            let state = filter_state.read().await;
+            (state.bass_boost, state.volume)
+        };
+
+        // Checked before processing, since a volume ramp changes this very frame
+        let unaltered = !bass_boost && target_volume == 1.0 && volume.is_unity();
+
+        if bass_boost {
             let mut fx = filters.lock().await;
             fx.apply(&mut frame, 2);
         }
 
-        /*let state = filter_state.read().await;
-        for s in frame.iter_mut() {
-            *s = (*s as f32 * state.volume).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
-        }*/
+        volume.apply(&mut frame, 2, target_volume);
 
-        send_voice_packet(&conn, &frame, &mut encoder, seq_val, ts_val).await?;
+        clip_buffer.lock().await.push(&frame);
+
+        // Filters and volume see every frame, so their state is current on a
+        // switch. The encoder doesn't while packets pass through: its history
+        // is from before passthrough started and has to be dropped.
+        let packet = opus.filter(|_| unaltered);
+        if packet.is_some() != passthrough {
+            passthrough = packet.is_some();
+            if !passthrough {
+                encoder.reset_state()?;
+            }
+            println!("[CONSUMER] Opus passthrough = {}", passthrough);
+        }
+        match packet {
+            Some(packet) => send_opus_packet(&*conn.read().await, &packet, seq_val, ts_val).await?,
+            None => {
+                send_voice_packet(&*conn.read().await, &frame, &mut encoder, seq_val, ts_val).await?
+            }
+        }
         seq_val = seq_val.wrapping_add(1);
         ts_val = ts_val.wrapping_add(960);
     }
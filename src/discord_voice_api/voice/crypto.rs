@@ -8,6 +8,44 @@ use chacha20poly1305::{
     aead::{Aead as ChaChaAead, Payload},
 };
 
+use std::fmt;
+
+/// Encryption modes we can handle, most preferred first
+pub const SUPPORTED_MODES: [&str; 2] = [
+    "aead_aes256_gcm_rtpsize",
+    "aead_xchacha20_poly1305_rtpsize",
+];
+
+/// None of the modes advertised in the voice READY payload are supported
+#[derive(Debug)]
+pub struct UnsupportedModesError {
+    pub offered: Vec<String>,
+}
+
+impl fmt::Display for UnsupportedModesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "no supported encryption mode offered (offered: [{}], supported: [{}])",
+            self.offered.join(", "),
+            SUPPORTED_MODES.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedModesError {}
+
+/// Picks the best mode out of the ones the voice server offers
+pub fn select_mode(offered: &[String]) -> Result<&'static str, UnsupportedModesError> {
+    SUPPORTED_MODES
+        .iter()
+        .find(|mode| offered.iter().any(|o| o == *mode))
+        .copied()
+        .ok_or_else(|| UnsupportedModesError {
+            offered: offered.to_vec(),
+        })
+}
+
 #[derive(Clone)]
 pub enum CipherMode {
     AES(Aes256Gcm),
@@ -72,4 +110,71 @@ impl CipherMode {
             }
         }
     }
+
+    /// Inverse of `encrypt_packet`. `ciphertext` is the encrypted payload incl. tag,
+    /// without the trailing 4-byte nonce counter.
+    pub fn decrypt_packet(
+        &self,
+        rtp_header: &[u8],
+        ciphertext: &[u8],
+        counter: u32,
+    ) -> Result<Vec<u8>> {
+        match self {
+            CipherMode::XChaCha(xchacha) => {
+                let mut nonce24 = [0u8; 24];
+                nonce24[..4].copy_from_slice(&counter.to_be_bytes());
+                let payload = Payload {
+                    aad: rtp_header,
+                    msg: ciphertext,
+                };
+                xchacha
+                    .decrypt(chacha20poly1305::XNonce::from_slice(&nonce24), payload)
+                    .map_err(|e| anyhow::anyhow!("XChaCha decryption failed: {:?}", e))
+            }
+            CipherMode::AES(aes) => {
+                let mut nonce12 = [0u8; 12];
+                nonce12[..4].copy_from_slice(&counter.to_be_bytes());
+                let aad = AesPayload {
+                    aad: rtp_header,
+                    msg: ciphertext,
+                };
+                aes.decrypt(aes_gcm::Nonce::from_slice(&nonce12), aad)
+                    .map_err(|e| anyhow::anyhow!("AES-GCM decrypt failed: {:?}", e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modes(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn prefers_aes_gcm() {
+        let offered = modes(&[
+            "aead_xchacha20_poly1305_rtpsize",
+            "aead_aes256_gcm_rtpsize",
+            "xsalsa20_poly1305",
+        ]);
+        assert_eq!(select_mode(&offered).unwrap(), "aead_aes256_gcm_rtpsize");
+    }
+
+    #[test]
+    fn falls_back_to_xchacha() {
+        let offered = modes(&["xsalsa20_poly1305", "aead_xchacha20_poly1305_rtpsize"]);
+        assert_eq!(select_mode(&offered).unwrap(), "aead_xchacha20_poly1305_rtpsize");
+    }
+
+    #[test]
+    fn unsupported_error_lists_offered_modes() {
+        let offered = modes(&["xsalsa20_poly1305", "xsalsa20_poly1305_lite"]);
+        let err = select_mode(&offered).unwrap_err();
+        assert_eq!(err.offered, offered);
+        let message = err.to_string();
+        assert!(message.contains("xsalsa20_poly1305, xsalsa20_poly1305_lite"), "{message}");
+    }
 }
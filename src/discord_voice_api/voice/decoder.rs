@@ -0,0 +1,80 @@
+use crate::discord_voice_api::voice::ffmpeg::{SourceInfo, spawn_ffmpeg_with_buffer};
+#[cfg(feature = "native-decoder")]
+use crate::discord_voice_api::voice::native_decoder::{NativeDecoder, spawn_native_decoder};
+use anyhow::Result;
+use tokio::io::AsyncRead;
+use tokio::process::Child;
+use tokio::time::Duration;
+
+/// Chunks buffered between the HTTP fetcher and the decoder
+const FETCH_BUFFER: usize = 64;
+
+/// Decoded s16le, 48 kHz, stereo PCM
+pub type PcmReader = Box<dyn AsyncRead + Unpin + Send>;
+
+/// Whatever turns a track into PCM
+pub enum DecoderHandle {
+    Ffmpeg(Child),
+    #[cfg(feature = "native-decoder")]
+    Native(NativeDecoder),
+}
+
+impl DecoderHandle {
+    pub async fn kill(&mut self) -> std::io::Result<()> {
+        match self {
+            DecoderHandle::Ffmpeg(child) => child.kill().await,
+            #[cfg(feature = "native-decoder")]
+            DecoderHandle::Native(decoder) => {
+                decoder.cancel();
+                Ok(())
+            }
+        }
+    }
+
+    /// `false` once the decoder has exited, i.e. no more PCM will arrive
+    pub fn is_running(&mut self) -> bool {
+        match self {
+            DecoderHandle::Ffmpeg(child) => child.try_wait().map(|s| s.is_none()).unwrap_or(true),
+            #[cfg(feature = "native-decoder")]
+            DecoderHandle::Native(decoder) => decoder.is_running(),
+        }
+    }
+
+    /// The source's Opus packet for the 20 ms of PCM starting at `offset`
+    /// (samples per channel). Only the native decoder demuxes Opus; with
+    /// ffmpeg every frame is encoded again.
+    #[cfg_attr(not(feature = "native-decoder"), allow(unused_variables))]
+    pub fn opus_packet_at(&mut self, offset: u64) -> Option<Vec<u8>> {
+        match self {
+            DecoderHandle::Ffmpeg(_) => None,
+            #[cfg(feature = "native-decoder")]
+            DecoderHandle::Native(decoder) => decoder.opus_packet_at(offset),
+        }
+    }
+}
+
+/// Starts decoding `url` at `start_at`. With the `native-decoder` feature,
+/// formats symphonia (or libopus) can handle are decoded in process;
+/// everything else, including HLS/DASH manifests, goes through ffmpeg.
+pub async fn spawn_decoder(
+    url: &str,
+    page_url: &str,
+    start_at: Duration,
+) -> Result<(DecoderHandle, PcmReader)> {
+    let client = reqwest::Client::new();
+    let source = SourceInfo::probe(&client, url).await;
+
+    #[cfg(feature = "native-decoder")]
+    if !source.is_manifest(url) {
+        match spawn_native_decoder(client.clone(), &source, url, page_url, FETCH_BUFFER, start_at)
+            .await
+        {
+            Ok((decoder, out)) => return Ok((DecoderHandle::Native(decoder), Box::new(out))),
+            Err(e) => println!("[DECODER] Falling back to ffmpeg: {e}"),
+        }
+    }
+
+    let (child, out) =
+        spawn_ffmpeg_with_buffer(client, &source, url, page_url, FETCH_BUFFER, start_at).await?;
+    Ok((DecoderHandle::Ffmpeg(child), Box::new(out)))
+}
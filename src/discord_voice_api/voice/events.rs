@@ -0,0 +1,13 @@
+use crate::discord_voice_api::voice::player::Track;
+
+/// Track lifecycle as seen by the producer, see `AudioPlayer::subscribe_events`
+#[derive(Clone, Debug)]
+pub enum TrackEvent {
+    Started(Track),
+    /// Played to the end, skipped, or replaced by a crossfade
+    Ended(Track),
+    CrossfadeStarted { from: Track, to: Track },
+    /// The track could not be started and was dropped
+    Errored { track: Track, error: String },
+    QueueFinished,
+}
@@ -1,82 +1,164 @@
+use crate::discord_voice_api::voice::resolver::resolve_stream_url;
 use anyhow::Result;
 use futures_util::StreamExt;
+use reqwest::StatusCode;
 use std::time::Duration;
+use tokio::sync::mpsc;
 use tokio::{io::AsyncWriteExt, process::Command as TokioCommand};
 
+const CHUNK_SIZE: u64 = 256 * 1024;
+/// Consecutive failed requests before the fetcher gives up on a track
+const MAX_RETRIES: u32 = 5;
+
+/// What googlevideo answers once a signed URL has expired
+fn is_expired_status(status: StatusCode) -> bool {
+    status == StatusCode::FORBIDDEN || status == StatusCode::GONE
+}
+
+/// Fresh media URL after the current one was rejected mid-track
+async fn re_resolve(page_url: &str, offset: u64) -> Option<String> {
+    println!("[FETCHER] 🔄 Stream URL expired at byte {}, re-resolving", offset);
+    match resolve_stream_url(page_url).await {
+        Ok(url) => Some(url),
+        Err(e) => {
+            eprintln!("[FETCHER] Could not re-resolve stream URL: {e}");
+            None
+        }
+    }
+}
+
+/// HLS/DASH playlists (live streams) that ffmpeg has to open itself,
+/// since their segments are separate requests
+fn is_manifest(url: &str, content_type: Option<&str>) -> bool {
+    let by_type = content_type.is_some_and(|t| {
+        let t = t.to_ascii_lowercase();
+        t.contains("mpegurl") || t.contains("dash+xml")
+    });
+    let path = url.split('?').next().unwrap_or(url);
+    by_type || path.ends_with(".m3u8") || path.ends_with(".mpd") || path.contains("/manifest/")
+}
+
+fn ffmpeg_args(input: &str, start_at: Duration) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+    if !start_at.is_zero() {
+        // Input option: seeks in input ffmpeg fetches itself, a pipe is
+        // decoded and discarded up to the target
+        args.extend(["-ss".to_string(), format!("{:.3}", start_at.as_secs_f64())]);
+    }
+    args.extend(
+        ["-i", input, "-f", "s16le", "-ar", "48000", "-ac", "2", "pipe:1"].map(String::from),
+    );
+    args
+}
+
+/// What a HEAD request tells about a media URL
+pub struct SourceInfo {
+    pub total_size: Option<u64>,
+    pub content_type: Option<String>,
+}
+
+impl SourceInfo {
+    pub async fn probe(client: &reqwest::Client, url: &str) -> Self {
+        // Live streams, HLS and chunked responses come without Content-Length.
+        // Error pages have one too, so it only counts on success.
+        match client.head(url).send().await {
+            Ok(resp) if resp.status().is_success() => {
+                let header = |name| {
+                    resp.headers()
+                        .get(name)
+                        .and_then(|h| h.to_str().ok())
+                        .map(str::to_string)
+                };
+                Self {
+                    total_size: header(reqwest::header::CONTENT_LENGTH)
+                        .and_then(|s| s.parse::<u64>().ok())
+                        .filter(|&size| size > 0),
+                    content_type: header(reqwest::header::CONTENT_TYPE),
+                }
+            }
+            _ => Self {
+                total_size: None,
+                content_type: None,
+            },
+        }
+    }
+
+    pub fn is_manifest(&self, url: &str) -> bool {
+        is_manifest(url, self.content_type.as_deref())
+    }
+}
+
+/// Downloads `url` from byte `start` into the returned channel in the
+/// background. `page_url` is where `url` was resolved from, for resolving
+/// it again when it expires mid-track. Sources of unknown size can only be
+/// streamed from the beginning.
+pub fn spawn_fetcher(
+    client: reqwest::Client,
+    url: &str,
+    page_url: &str,
+    source: &SourceInfo,
+    buffer_size: usize,
+    start: u64,
+) -> mpsc::Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel::<Vec<u8>>(buffer_size);
+    let url_owned = url.to_string();
+    let page_url = page_url.to_string();
+    let total_size = source.total_size;
+
+    tokio::spawn(async move {
+        match total_size {
+            Some(total_size) => {
+                println!("[FETCHER] Total size: {} bytes", total_size);
+                fetch_ranged(&client, url_owned, &page_url, start, total_size, &tx).await;
+            }
+            None => {
+                println!("[FETCHER] Unknown size, streaming");
+                fetch_streaming(&client, url_owned, &page_url, &tx).await;
+            }
+        }
+        println!("[FETCHER] ✅ Finished downloading stream.");
+    });
+
+    rx
+}
+
 pub async fn spawn_ffmpeg_with_buffer(
+    client: reqwest::Client,
+    source: &SourceInfo,
     url: &str,
+    page_url: &str,
     buffer_size: usize,
+    start_at: Duration,
 ) -> Result<(tokio::process::Child, tokio::process::ChildStdout)> {
-    let args = [
-        "-i", "pipe:0",
-        "-f", "s16le",
-        "-ar", "48000",
-        "-ac", "2",
-        "pipe:1"
-    ];
+    // ffmpeg can only seek in input it fetches itself. That gives up
+    // re-resolving an expired URL, but piping would mean downloading and
+    // decoding everything before the target first.
+    let seekable = !start_at.is_zero() && source.total_size.is_some();
+    if source.is_manifest(url) || seekable {
+        println!("[FETCHER] Handing {} to ffmpeg", if seekable { "seek" } else { "manifest" });
+        let mut child = TokioCommand::new("ffmpeg")
+            .args(ffmpeg_args(url, start_at))
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let ffmpeg_stdout = child.stdout.take().expect("child stdout");
+        return Ok((child, ffmpeg_stdout));
+    }
 
     let mut child = TokioCommand::new("ffmpeg")
-        .args(args)
+        .args(ffmpeg_args("pipe:0", start_at))
         .stdin(std::process::Stdio::piped())
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
         .spawn()?;
 
     let mut ffmpeg_stdin = child.stdin.take().expect("child stdin");
     let ffmpeg_stdout = child.stdout.take().expect("child stdout");
 
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(buffer_size);
-    let url_owned = url.to_string();
-
-    tokio::spawn(async move {
-        let client = reqwest::Client::new();
-        let total_size = match client.head(&url_owned).send().await {
-            Ok(resp) => resp
-                .headers()
-                .get(reqwest::header::CONTENT_LENGTH)
-                .and_then(|h| h.to_str().ok())
-                .and_then(|s| s.parse::<u64>().ok())
-                .unwrap_or(0),
-            Err(_) => 0,
-        };
-
-        println!("[FETCHER] Total size: {} bytes", total_size);
-
-        let mut start: u64 = 0;
-        let chunk_size: u64 = 256 * 1024;
-
-        while start < total_size {
-            let end = (start + chunk_size - 1).min(total_size - 1);
-            let range = format!("bytes={}-{}", start, end);
-
-            match client.get(&url_owned).header("Range", range).send().await {
-                Ok(r) => {
-                    let mut stream = r.bytes_stream();
-                    while let Some(chunk) = stream.next().await {
-                        match chunk {
-                            Ok(bytes) => {
-                                start += bytes.len() as u64;
-                                if tx.send(bytes.to_vec()).await.is_err() {
-                                    return;
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!("[FETCHER] Chunk error: {e}");
-                                tokio::time::sleep(Duration::from_secs(1)).await;
-                                break;
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("[FETCHER] HTTP error: {e}");
-                    tokio::time::sleep(Duration::from_secs(2)).await;
-                }
-            }
-        }
-
-        println!("[FETCHER] ✅ Finished downloading stream.");
-    });
+    let mut rx = spawn_fetcher(client, url, page_url, source, buffer_size, 0);
 
     tokio::spawn(async move {
         while let Some(buf) = rx.recv().await {
@@ -90,3 +172,115 @@ pub async fn spawn_ffmpeg_with_buffer(
 
     Ok((child, ffmpeg_stdout))
 }
+
+/// Downloads `url` in range requests from byte `start`, retrying failed
+/// chunks. An expired URL is resolved again once and resumed from the
+/// current offset.
+async fn fetch_ranged(
+    client: &reqwest::Client,
+    mut url: String,
+    page_url: &str,
+    mut start: u64,
+    total_size: u64,
+    tx: &mpsc::Sender<Vec<u8>>,
+) {
+    let mut re_resolved = false;
+    let mut failures = 0;
+
+    while start < total_size {
+        if failures >= MAX_RETRIES {
+            eprintln!("[FETCHER] Giving up at byte {} of {}", start, total_size);
+            return;
+        }
+
+        let end = (start + CHUNK_SIZE - 1).min(total_size - 1);
+        let range = format!("bytes={}-{}", start, end);
+
+        match client.get(&url).header("Range", range).send().await {
+            Ok(r) if is_expired_status(r.status()) => {
+                if re_resolved {
+                    eprintln!("[FETCHER] Re-resolved URL rejected too: {}", r.status());
+                    return;
+                }
+                re_resolved = true;
+                match re_resolve(page_url, start).await {
+                    Some(new_url) => url = new_url,
+                    None => return,
+                }
+            }
+            Ok(r) if !r.status().is_success() => {
+                eprintln!("[FETCHER] HTTP status: {}", r.status());
+                failures += 1;
+                tokio::time::sleep(Duration::from_secs(2)).await;
+            }
+            Ok(r) => {
+                let mut stream = r.bytes_stream();
+                while let Some(chunk) = stream.next().await {
+                    match chunk {
+                        Ok(bytes) => {
+                            start += bytes.len() as u64;
+                            failures = 0;
+                            if tx.send(bytes.to_vec()).await.is_err() {
+                                return;
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("[FETCHER] Chunk error: {e}");
+                            failures += 1;
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("[FETCHER] HTTP error: {e}");
+                failures += 1;
+                tokio::time::sleep(Duration::from_secs(2)).await;
+            }
+        }
+    }
+}
+
+/// Passes a single GET through until the server closes it. Without a
+/// known size there is no range to resume from, so an error ends the track.
+async fn fetch_streaming(
+    client: &reqwest::Client,
+    mut url: String,
+    page_url: &str,
+    tx: &mpsc::Sender<Vec<u8>>,
+) {
+    let mut resp = client.get(&url).send().await;
+    if let Ok(r) = &resp {
+        if is_expired_status(r.status()) {
+            match re_resolve(page_url, 0).await {
+                Some(new_url) => url = new_url,
+                None => return,
+            }
+            resp = client.get(&url).send().await;
+        }
+    }
+
+    let resp = match resp.and_then(|r| r.error_for_status()) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("[FETCHER] HTTP error: {e}");
+            return;
+        }
+    };
+
+    let mut stream = resp.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(bytes) => {
+                if tx.send(bytes.to_vec()).await.is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                eprintln!("[FETCHER] Stream error: {e}");
+                return;
+            }
+        }
+    }
+}
@@ -1,9 +1,20 @@
+pub mod clip;
 pub mod connection;
 mod consumer;
 pub mod crypto;
+mod decoder;
+pub mod events;
 mod ffmpeg;
+#[cfg(feature = "native-decoder")]
+mod native_decoder;
+pub mod ogg_writer;
 pub mod player;
+mod prefetch;
 mod producer;
+pub mod receiver;
+mod resampler;
+pub mod resolver;
+pub mod recorder;
 pub mod audio_commands;
 
 pub use connection::VoiceConnection;
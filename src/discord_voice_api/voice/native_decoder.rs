@@ -0,0 +1,508 @@
+use crate::discord_voice_api::voice::ffmpeg::{SourceInfo, spawn_fetcher};
+use crate::discord_voice_api::voice::resampler::Resampler;
+use anyhow::{Result, anyhow};
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{CODEC_TYPE_NULL, CODEC_TYPE_OPUS, CodecParameters, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, Packet, SeekMode, SeekTo};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::{Time, TimeBase};
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+
+const OUTPUT_RATE: u32 = 48000;
+/// Bytes of decoded PCM buffered towards the producer (~1 s)
+const PIPE_SIZE: usize = 192 * 1024;
+/// Largest Opus packet: 120 ms of 48 kHz stereo
+const MAX_OPUS_SAMPLES: usize = 5760 * 2;
+/// Samples per channel in a 20 ms frame
+const FRAME_SAMPLES: usize = 960;
+/// Opus packets kept for passthrough. The decoder runs ahead of playback
+/// by the pipe, the prefetched head and the crossfade, ~10 s covers that.
+const PACKET_BUFFER: usize = 512;
+/// Forward seeks up to this far read through instead of restarting the download
+const READ_THROUGH: u64 = 256 * 1024;
+/// libopus needs a few packets to converge after a jump
+const OPUS_PREROLL: Duration = Duration::from_millis(80);
+
+/// A source Opus packet and where its audio starts in the PCM output
+struct OpusPacket {
+    /// Samples per channel written before this packet's audio
+    offset: u64,
+    data: Vec<u8>,
+}
+
+/// In-process decoder running on a blocking thread
+pub struct NativeDecoder {
+    cancelled: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+    packets: mpsc::Receiver<OpusPacket>,
+    /// Received, but for audio that hasn't been played yet
+    pending: Option<OpusPacket>,
+}
+
+impl NativeDecoder {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.thread.is_finished()
+    }
+
+    /// The source packet whose audio starts at output sample `offset`, if any.
+    /// Packets for audio before `offset` are discarded.
+    pub fn opus_packet_at(&mut self, offset: u64) -> Option<Vec<u8>> {
+        loop {
+            let packet = match self.pending.take() {
+                Some(p) => p,
+                None => self.packets.try_recv().ok()?,
+            };
+            if packet.offset == offset {
+                return Some(packet.data);
+            }
+            if packet.offset > offset {
+                self.pending = Some(packet);
+                return None;
+            }
+        }
+    }
+}
+
+/// What a seekable source restarts the fetcher with
+struct Refetch {
+    client: reqwest::Client,
+    url: String,
+    page_url: String,
+    source: SourceInfo,
+    total_size: u64,
+    buffer_size: usize,
+    runtime: Handle,
+}
+
+/// Blocking `Read` over the fetcher's chunks, for symphonia
+struct ChannelReader {
+    rx: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    /// Source byte offset of `chunk[pos]`
+    offset: u64,
+    /// Set if the source can be downloaded from any offset
+    refetch: Option<Refetch>,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos >= self.chunk.len() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for ChannelReader {
+    fn seek(&mut self, to: SeekFrom) -> std::io::Result<u64> {
+        let total_size = self.byte_len().ok_or(std::io::ErrorKind::Unsupported)?;
+        let target = match to {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(delta) => self.offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => total_size.checked_add_signed(delta),
+        }
+        .ok_or(std::io::ErrorKind::InvalidInput)?;
+
+        let chunk_start = self.offset - self.pos as u64;
+        if (chunk_start..self.offset).contains(&target) {
+            self.pos -= (self.offset - target) as usize;
+            self.offset = target;
+            return Ok(target);
+        }
+        if target >= self.offset && target - self.offset <= READ_THROUGH {
+            let ahead = target - self.offset;
+            std::io::copy(&mut (&mut *self).take(ahead), &mut std::io::sink())?;
+            if self.offset == target {
+                return Ok(target);
+            }
+        }
+
+        let refetch = self.refetch.as_ref().expect("seekable");
+        let _runtime = refetch.runtime.enter();
+        self.rx = spawn_fetcher(
+            refetch.client.clone(),
+            &refetch.url,
+            &refetch.page_url,
+            &refetch.source,
+            refetch.buffer_size,
+            target,
+        );
+        self.chunk.clear();
+        self.pos = 0;
+        self.offset = target;
+        Ok(target)
+    }
+}
+
+impl MediaSource for ChannelReader {
+    fn is_seekable(&self) -> bool {
+        self.refetch.is_some()
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.refetch.as_ref().map(|r| r.total_size)
+    }
+}
+
+/// symphonia has no Opus decoder, so WebM/Ogg Opus goes through libopus
+enum Codec {
+    Opus(opus::Decoder),
+    Symphonia(Box<dyn symphonia::core::codecs::Decoder>),
+}
+
+impl Codec {
+    /// Interleaved samples of one packet, in the track's channel layout
+    fn decode(&mut self, packet: &Packet) -> Result<Vec<i16>> {
+        match self {
+            Codec::Opus(decoder) => {
+                let mut pcm = vec![0i16; MAX_OPUS_SAMPLES];
+                let samples = decoder.decode(&packet.data, &mut pcm, false)?;
+                pcm.truncate(samples * 2);
+                Ok(pcm)
+            }
+            Codec::Symphonia(decoder) => {
+                let decoded = decoder.decode(packet)?;
+                let mut buf = SampleBuffer::<i16>::new(decoded.capacity() as u64, *decoded.spec());
+                buf.copy_interleaved_ref(decoded);
+                Ok(buf.samples().to_vec())
+            }
+        }
+    }
+}
+
+fn to_stereo(samples: &[i16], channels: usize) -> Vec<i16> {
+    match channels {
+        2 => samples.to_vec(),
+        1 => samples.iter().flat_map(|&s| [s, s]).collect(),
+        n => samples.chunks_exact(n).flat_map(|c| [c[0], c[1]]).collect(),
+    }
+}
+
+/// Probes the stream and starts decoding it at `start_at`. Fails (so the
+/// caller can fall back to ffmpeg) if the format or codec is unsupported.
+pub async fn spawn_native_decoder(
+    client: reqwest::Client,
+    source: &SourceInfo,
+    url: &str,
+    page_url: &str,
+    buffer_size: usize,
+    start_at: Duration,
+) -> Result<(NativeDecoder, DuplexStream)> {
+    let rx = spawn_fetcher(client.clone(), url, page_url, source, buffer_size, 0);
+    // Probing a seekable source jumps around the file, which costs extra
+    // requests. Only worth it to reach `start_at` without downloading
+    // everything before it.
+    let refetch = match source.total_size {
+        Some(total_size) if !start_at.is_zero() => Some(Refetch {
+            client,
+            url: url.to_string(),
+            page_url: page_url.to_string(),
+            source: SourceInfo {
+                total_size: Some(total_size),
+                content_type: source.content_type.clone(),
+            },
+            total_size,
+            buffer_size,
+            runtime: Handle::current(),
+        }),
+        _ => None,
+    };
+    let (writer, reader) = tokio::io::duplex(PIPE_SIZE);
+    let (ready_tx, ready_rx) = oneshot::channel();
+    let (packets_tx, packets) = mpsc::channel(PACKET_BUFFER);
+    let cancelled = Arc::new(AtomicBool::new(false));
+
+    let mut hint = Hint::new();
+    if let Some(content_type) = &source.content_type {
+        hint.mime_type(content_type);
+    }
+
+    let thread = {
+        let cancelled = cancelled.clone();
+        let runtime = Handle::current();
+        tokio::task::spawn_blocking(move || {
+            let reader = ChannelReader {
+                rx,
+                chunk: Vec::new(),
+                pos: 0,
+                offset: 0,
+                refetch,
+            };
+            let mut stream = match open(reader, &hint) {
+                Ok(stream) => {
+                    let _ = ready_tx.send(Ok(()));
+                    stream
+                }
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                    return;
+                }
+            };
+            let out = Output {
+                writer,
+                packets: packets_tx,
+                runtime: &runtime,
+                skip: seek_skip(seek(&mut stream, start_at)),
+                written: 0,
+            };
+            decode(stream, out, &cancelled);
+        })
+    };
+
+    ready_rx.await.map_err(|_| anyhow!("Decoder thread died"))??;
+    println!("[DECODER] Decoding natively");
+    let decoder = NativeDecoder {
+        cancelled,
+        thread,
+        packets,
+        pending: None,
+    };
+    Ok((decoder, reader))
+}
+
+/// Jumps to just before `start_at` if the source allows it. Returns how much
+/// decoded audio is left to drop, all of it for sources that can't seek.
+fn seek(stream: &mut OpenStream, start_at: Duration) -> Duration {
+    let Some(time_base) = stream.time_base.filter(|_| !start_at.is_zero()) else {
+        return start_at;
+    };
+    let preroll = match stream.codec {
+        Codec::Opus(_) => OPUS_PREROLL,
+        Codec::Symphonia(_) => Duration::ZERO,
+    };
+    let to = SeekTo::Time {
+        time: Time::from(start_at.saturating_sub(preroll).as_secs_f64()),
+        track_id: Some(stream.track_id),
+    };
+    match stream.format.seek(SeekMode::Coarse, to) {
+        Ok(seeked) => {
+            // Decoding now starts mid-stream, past the encoder delay
+            stream.pre_skip = 0;
+            let reached = time_base.calc_time(seeked.actual_ts);
+            let reached = Duration::from_secs_f64(reached.seconds as f64 + reached.frac);
+            println!("[DECODER] Seeked to {:.3}s", reached.as_secs_f64());
+            start_at.saturating_sub(reached)
+        }
+        Err(e) => {
+            println!("[DECODER] Can't seek, decoding up to the target: {e}");
+            start_at
+        }
+    }
+}
+
+/// Samples per channel to drop for a seek to `start_at`. Rounded to whole
+/// frames so the packets after it still start on the producer's frames;
+/// `/forward` and `/rewind` land a hair off them after summing 20 ms steps.
+fn seek_skip(start_at: Duration) -> usize {
+    let frames = start_at.as_secs_f64() * OUTPUT_RATE as f64 / FRAME_SAMPLES as f64;
+    frames.round() as usize * FRAME_SAMPLES
+}
+
+struct OpenStream {
+    format: Box<dyn FormatReader>,
+    codec: Codec,
+    track_id: u32,
+    sample_rate: u32,
+    channels: usize,
+    time_base: Option<TimeBase>,
+    /// Opus encoder delay: samples per channel at the start that aren't audio
+    pre_skip: usize,
+}
+
+/// Pre-skip from the Opus header. The Ogg reader parses it into `delay`,
+/// WebM only passes the raw `OpusHead` along as extra data.
+fn opus_pre_skip(params: &CodecParameters) -> usize {
+    if let Some(delay) = params.delay {
+        return delay as usize;
+    }
+    match params.extra_data.as_deref() {
+        Some(head) if head.len() >= 12 && head.starts_with(b"OpusHead") => {
+            u16::from_le_bytes([head[10], head[11]]) as usize
+        }
+        _ => 0,
+    }
+}
+
+fn open(reader: ChannelReader, hint: &Hint) -> Result<OpenStream> {
+    let stream = MediaSourceStream::new(Box::new(reader), Default::default());
+    let probed = symphonia::default::get_probe().format(
+        hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    let format = probed.format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| anyhow!("No audio track"))?;
+    let params = &track.codec_params;
+
+    let (codec, sample_rate, channels, pre_skip) = if params.codec == CODEC_TYPE_OPUS {
+        // libopus decodes to 48 kHz stereo whatever the stream layout
+        let decoder = opus::Decoder::new(OUTPUT_RATE, opus::Channels::Stereo)?;
+        (Codec::Opus(decoder), OUTPUT_RATE, 2, opus_pre_skip(params))
+    } else {
+        let decoder = symphonia::default::get_codecs().make(params, &DecoderOptions::default())?;
+        let rate = params.sample_rate.ok_or_else(|| anyhow!("Unknown sample rate"))?;
+        let channels = params.channels.map(|c| c.count()).unwrap_or(2);
+        (Codec::Symphonia(decoder), rate, channels, 0)
+    };
+
+    let track_id = track.id;
+    let time_base = params.time_base;
+    Ok(OpenStream {
+        format,
+        codec,
+        track_id,
+        sample_rate,
+        channels,
+        time_base,
+        pre_skip,
+    })
+}
+
+/// Writes decoded PCM to the producer and reports passthrough packets
+struct Output<'a> {
+    writer: DuplexStream,
+    packets: mpsc::Sender<OpusPacket>,
+    runtime: &'a Handle,
+    /// Samples per channel still to drop, from where seeking got to the target
+    skip: usize,
+    /// Samples per channel written so far
+    written: u64,
+}
+
+impl Output<'_> {
+    /// `false` once the producer dropped the stream
+    fn write(&mut self, stereo: &[i16], packet: Option<&[u8]>) -> bool {
+        let dropped = self.skip.min(stereo.len() / 2);
+        self.skip -= dropped;
+        let stereo = &stereo[dropped * 2..];
+        if stereo.is_empty() {
+            return true;
+        }
+
+        if let Some(data) = packet.filter(|_| dropped == 0) {
+            // Best effort: without the packet the frame is simply encoded again
+            let _ = self.packets.try_send(OpusPacket {
+                offset: self.written,
+                data: data.to_vec(),
+            });
+        }
+        self.written += (stereo.len() / 2) as u64;
+
+        let bytes: Vec<u8> = stereo.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.runtime.block_on(self.writer.write_all(&bytes)).is_ok()
+    }
+}
+
+/// Runs until the stream ends, fails, or the decoder is cancelled or its
+/// output dropped. Output is resampled to 48 kHz in 20 ms frames; Opus is
+/// already 48 kHz and passes through packet by packet.
+fn decode(mut stream: OpenStream, mut out: Output, cancelled: &AtomicBool) {
+    let step = stream.sample_rate as f64 / OUTPUT_RATE as f64;
+    let mut resampler = Resampler::new();
+
+    while !cancelled.load(Ordering::Relaxed) {
+        let packet = match stream.format.next_packet() {
+            Ok(p) => p,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => {
+                eprintln!("[DECODER] Read error: {e}");
+                break;
+            }
+        };
+        if packet.track_id() != stream.track_id {
+            continue;
+        }
+
+        let samples = match stream.codec.decode(&packet) {
+            Ok(s) => s,
+            Err(e) => {
+                // A corrupt packet only costs a few milliseconds
+                eprintln!("[DECODER] Skipping packet: {e}");
+                continue;
+            }
+        };
+        let mut stereo = to_stereo(&samples, stream.channels);
+
+        if let Codec::Opus(_) = stream.codec {
+            // Discord expects 20 ms packets, others are only played as PCM
+            let mut passthrough = (stereo.len() == FRAME_SAMPLES * 2).then_some(&*packet.data);
+            if stream.pre_skip > 0 {
+                let frames = stereo.len() / 2;
+                if stream.pre_skip >= frames {
+                    stream.pre_skip -= frames;
+                    continue;
+                }
+                // Silenced instead of dropped, so packets stay on the 20 ms
+                // frame grid. The packet itself would play the skipped part.
+                stereo[..stream.pre_skip * 2].fill(0);
+                stream.pre_skip = 0;
+                passthrough = None;
+            }
+            if !out.write(&stereo, passthrough) {
+                return;
+            }
+            continue;
+        }
+
+        let bytes: Vec<u8> = stereo.iter().flat_map(|s| s.to_le_bytes()).collect();
+        resampler.push_bytes(&bytes);
+        while let Some(frame) = resampler.next_frame(step) {
+            if !out.write(&frame, None) {
+                return;
+            }
+        }
+    }
+
+    let _ = out.runtime.block_on(out.writer.shutdown());
+    println!("[DECODER] Input stream closed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seek_skip_is_whole_frames() {
+        assert_eq!(seek_skip(Duration::ZERO), 0);
+        assert_eq!(seek_skip(Duration::from_secs(30)), 30 * 48000);
+        assert_eq!(seek_skip(Duration::from_millis(1505)), 75 * FRAME_SAMPLES);
+    }
+
+    #[test]
+    fn seek_skip_absorbs_summed_frame_steps() {
+        let played: f64 = (0..1500).map(|_| 0.02).sum();
+        assert!(played != 30.0);
+        assert_eq!(seek_skip(Duration::from_secs_f64(played)), 1_440_000);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::discord_voice_api::udp::send_packet::OPUS_SILENCE_FRAME;
+use anyhow::Result;
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use opus::{Application, Channels, Encoder};
+use std::io::Write;
+
+pub const SAMPLES_PER_FRAME: u64 = 960; // 20 ms @ 48 kHz, per channel
+const PRE_SKIP: u16 = 312;
+
+/// Encodes s16le 48 kHz stereo frames into an Ogg/Opus stream
+pub struct OggOpusWriter<W: Write> {
+    writer: PacketWriter<W>,
+    encoder: Encoder,
+    serial: u32,
+    frames: u64,
+}
+
+impl<W: Write> OggOpusWriter<W> {
+    pub fn new(inner: W) -> Result<Self> {
+        let mut writer = PacketWriter::new(inner);
+        let serial = rand::random::<u32>();
+
+        // OpusHead
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.push(1); // version
+        head.push(2); // channels
+        head.extend_from_slice(&PRE_SKIP.to_le_bytes());
+        head.extend_from_slice(&48000u32.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // mapping family
+        writer.write_packet(head.into_boxed_slice(), serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        // OpusTags
+        let vendor = b"Rusty MetalFistBot 7000";
+        let mut tags = Vec::new();
+        tags.extend_from_slice(b"OpusTags");
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor);
+        tags.extend_from_slice(&0u32.to_le_bytes()); // no user comments
+        writer.write_packet(tags.into_boxed_slice(), serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        Ok(Self {
+            writer,
+            encoder: Encoder::new(48000, Channels::Stereo, Application::Audio)?,
+            serial,
+            frames: 0,
+        })
+    }
+
+    /// Number of 20 ms frames written so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    fn granule(&self) -> u64 {
+        PRE_SKIP as u64 + self.frames * SAMPLES_PER_FRAME
+    }
+
+    /// Writes one 20 ms frame; shorter input is padded with silence
+    pub fn write_frame(&mut self, pcm: &[i16]) -> Result<()> {
+        let mut frame = [0i16; (SAMPLES_PER_FRAME * 2) as usize];
+        let n = pcm.len().min(frame.len());
+        frame[..n].copy_from_slice(&pcm[..n]);
+
+        let mut opus_buf = vec![0u8; 1000];
+        let len = self.encoder.encode(&frame, &mut opus_buf)?;
+        opus_buf.truncate(len);
+
+        self.write_packet(opus_buf, PacketWriteEndInfo::NormalPacket)
+    }
+
+    pub fn write_silence(&mut self, frames: u64) -> Result<()> {
+        for _ in 0..frames {
+            self.write_packet(OPUS_SILENCE_FRAME.to_vec(), PacketWriteEndInfo::NormalPacket)?;
+        }
+        Ok(())
+    }
+
+    fn write_packet(&mut self, packet: Vec<u8>, info: PacketWriteEndInfo) -> Result<()> {
+        self.frames += 1;
+        let granule = self.granule();
+        self.writer
+            .write_packet(packet.into_boxed_slice(), self.serial, info, granule)?;
+        Ok(())
+    }
+
+    /// Closes the logical stream and hands back the underlying writer
+    pub fn finish(mut self) -> Result<W> {
+        self.write_packet(OPUS_SILENCE_FRAME.to_vec(), PacketWriteEndInfo::EndStream)?;
+        let mut inner = self.writer.into_inner();
+        inner.flush()?;
+        Ok(inner)
+    }
+}
@@ -1,22 +1,56 @@
 use super::{consumer::audio_consumer, producer::audio_producer};
-use crate::discord_voice_api::voice::connection::{VoiceConnection, VoiceSession};
+use super::events::TrackEvent;
+use super::clip::{CLIP_SECONDS, ClipBuffer, SharedClipBuffer, encode_clip};
+use super::receiver::{ReceivedAudio, audio_receiver};
+use super::recorder::Recording;
+use crate::discord_voice_api::udp::send_packet::{OPUS_SILENCE_FRAME, send_opus_packet};
+use crate::discord_voice_api::voice::connection::{SharedVoiceConnection, VoiceSession};
 use crate::discord_voice_api::voice::audio_commands::{
     AudioCommand, AudioFilterState, AudioFilters, SharedAudioFilters,
 };
 use anyhow::Result;
 use serde::Deserialize;
-use std::collections::VecDeque;
+use rand::Rng;
+use std::collections::{HashSet, VecDeque};
+use std::path::PathBuf;
+use std::time::SystemTime;
 use std::sync::{
     Arc,
-    atomic::{AtomicU16, AtomicU32},
+    atomic::{AtomicU16, AtomicU32, Ordering},
 };
-use tokio::sync::{Mutex, RwLock, mpsc};
+use tokio::sync::{Mutex, RwLock, broadcast, mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, timeout};
 
 pub type AudioFrame = Vec<i16>;
 
+/// What the producer hands the consumer every 20 ms
+pub struct PlaybackFrame {
+    pub pcm: AudioFrame,
+    /// The source's own Opus packet for exactly this audio. The consumer
+    /// sends it instead of encoding `pcm` while no filter or volume change
+    /// alters the sound. Only the `native-decoder` feature provides these.
+    pub opus: Option<Vec<u8>>,
+}
+
 pub const FRAME_SIZE: usize = 960 * 2 * 2;
 pub const FADE_SEC: f64 = 8.0;
 pub const BUFFER_FRAMES: usize = 100;
+pub const RECEIVE_BUFFER_FRAMES: usize = 500;
+pub const MAX_HISTORY: usize = 50;
+pub const EVENT_BUFFER: usize = 32;
+
+/// Where playback currently is, published by the producer every frame
+#[derive(Clone, Debug, Default)]
+pub struct PlaybackPosition {
+    /// Seconds into the current track (track time, not wall time)
+    pub seconds: f64,
+    pub paused: bool,
+    /// Title of the track being faded in, while a crossfade runs
+    pub crossfading_into: Option<String>,
+}
+
+pub type SharedPlaybackPosition = Arc<RwLock<PlaybackPosition>>;
 
 #[derive(Debug, Deserialize, Clone, Default)]
 pub struct Track {
@@ -24,20 +58,100 @@ pub struct Track {
     pub title: String,
     pub duration: Option<f64>,
     pub thumbnail: Option<String>,
+    /// Direct media URL. Signed URLs expire after a few hours, so this is
+    /// resolved from `page_url()` right before playback instead of at enqueue time.
+    #[serde(skip)]
     pub url: Option<String>,
+    pub webpage_url: Option<String>,
+    pub uploader: Option<String>,
+    pub channel: Option<String>,
+    pub is_live: Option<bool>,
+    /// Discord user ID of whoever queued the track
+    #[serde(skip)]
+    pub requested_by: Option<String>,
+    #[serde(skip)]
+    pub requested_at: Option<SystemTime>,
+}
+
+impl Track {
+    /// Page the stream URL was resolved from
+    pub fn page_url(&self) -> String {
+        match &self.webpage_url {
+            Some(url) => url.clone(),
+            // Older yt-dlp output without `webpage_url` is YouTube only
+            None => format!("https://www.youtube.com/watch?v={}", self.id),
+        }
+    }
+
+    /// Uploader, falling back to the channel name
+    pub fn artist(&self) -> Option<&str> {
+        self.uploader.as_deref().or(self.channel.as_deref())
+    }
+
+    pub fn live(&self) -> bool {
+        self.is_live.unwrap_or(false)
+    }
+
+    /// Length of the track if it has a fixed end. yt-dlp reports the time
+    /// streamed so far as `duration` for live streams, which is not one.
+    pub fn known_duration(&self) -> Option<f64> {
+        self.duration.filter(|_| !self.live())
+    }
+
+    pub fn set_requester(&mut self, user_id: String) {
+        self.requested_by = Some(user_id);
+        self.requested_at = Some(SystemTime::now());
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoopMode {
+    #[default]
+    Off,
+    Track,
+    Queue,
+}
+
+impl LoopMode {
+    /// Off → Track → Queue → Off
+    pub fn next(self) -> Self {
+        match self {
+            LoopMode::Off => LoopMode::Track,
+            LoopMode::Track => LoopMode::Queue,
+            LoopMode::Queue => LoopMode::Off,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            LoopMode::Off => "➡️ Off",
+            LoopMode::Track => "🔂 Track",
+            LoopMode::Queue => "🔁 Queue",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    pub track: Track,
+    pub played_at: SystemTime,
 }
 
 #[derive(Clone)]
 pub struct TrackQueue {
     inner: Arc<Mutex<VecDeque<Track>>>,
-    current_track: Arc<Mutex<Option<Track>>>,
+    current_track: Arc<watch::Sender<Option<Track>>>,
+    loop_mode: Arc<Mutex<LoopMode>>,
+    history: Arc<Mutex<VecDeque<HistoryEntry>>>,
 }
 
 impl TrackQueue {
     pub fn new() -> Self {
         Self {
             inner: Arc::new(Mutex::new(VecDeque::new())),
-            current_track: Arc::new(Mutex::new(None)),
+            current_track: Arc::new(watch::Sender::new(None)),
+            loop_mode: Arc::new(Mutex::new(LoopMode::Off)),
+            history: Arc::new(Mutex::new(VecDeque::new())),
         }
     }
 
@@ -46,11 +160,32 @@ impl TrackQueue {
         queue.push_back(track);
     }
 
+    pub async fn push_front(&self, track: Track) {
+        let mut queue = self.inner.lock().await;
+        queue.push_front(track);
+    }
+
     pub async fn pop(&self) -> Option<Track> {
         let mut queue = self.inner.lock().await;
         queue.pop_front()
     }
 
+    /// Pops the next track only if it is still the one with `id`
+    pub async fn pop_if(&self, id: &str) -> Option<Track> {
+        let mut queue = self.inner.lock().await;
+        if queue.front().is_some_and(|t| t.id == id) {
+            queue.pop_front()
+        } else {
+            None
+        }
+    }
+
+    /// Clones of the next `n` tracks
+    pub async fn peek(&self, n: usize) -> Vec<Track> {
+        let queue = self.inner.lock().await;
+        queue.iter().take(n).cloned().collect()
+    }
+
     pub async fn is_empty(&self) -> bool {
         let queue = self.inner.lock().await;
         queue.is_empty()
@@ -66,44 +201,205 @@ impl TrackQueue {
         queue.iter().cloned().collect()
     }
 
+    /// Removes the track at `index` (0-based), `None` if out of range
+    pub async fn remove(&self, index: usize) -> Option<Track> {
+        let mut queue = self.inner.lock().await;
+        queue.remove(index)
+    }
+
+    /// Moves the track at `from` to `to` (both 0-based), `None` if either is out of range
+    pub async fn move_track(&self, from: usize, to: usize) -> Option<Track> {
+        let mut queue = self.inner.lock().await;
+        if from >= queue.len() || to >= queue.len() {
+            return None;
+        }
+        let track = queue.remove(from)?;
+        queue.insert(to, track.clone());
+        Some(track)
+    }
+
+    /// Drops every queued track and returns how many there were.
+    /// The current track keeps playing.
+    pub async fn clear(&self) -> usize {
+        let mut queue = self.inner.lock().await;
+        let count = queue.len();
+        queue.clear();
+        count
+    }
+
+    /// Fisher–Yates shuffle of the upcoming tracks; the current track is not part of the queue
+    pub async fn shuffle(&self) {
+        let mut queue = self.inner.lock().await;
+        let mut rng = rand::rng();
+        for i in (1..queue.len()).rev() {
+            let j = rng.random_range(0..=i);
+            queue.swap(i, j);
+        }
+    }
+
+    /// Removes repeated tracks (same id), keeping the first occurrence.
+    /// Returns the number of removed tracks.
+    pub async fn dedupe(&self) -> usize {
+        let mut queue = self.inner.lock().await;
+        let before = queue.len();
+        let mut seen = HashSet::new();
+        queue.retain(|track| seen.insert(track.id.clone()));
+        before - queue.len()
+    }
+
     pub async fn set_current_track(&self, track: Track) {
-        let mut curr = self.current_track.lock().await;
-        *curr = Some(track);
+        self.current_track.send_replace(Some(track));
     }
 
     pub async fn get_current_track(&self) -> Option<Track> {
-        let curr = self.current_track.lock().await;
-        curr.clone()
+        self.current_track.borrow().clone()
     }
 
     pub async fn clear_current_track(&self) {
-        let mut curr = self.current_track.lock().await;
-        *curr = None;
+        self.current_track.send_replace(None);
+    }
+
+    /// Notified whenever the current track changes (or playback stops)
+    pub fn watch_current_track(&self) -> watch::Receiver<Option<Track>> {
+        self.current_track.subscribe()
+    }
+
+    pub async fn loop_mode(&self) -> LoopMode {
+        *self.loop_mode.lock().await
+    }
+
+    pub async fn set_loop_mode(&self, mode: LoopMode) {
+        *self.loop_mode.lock().await = mode;
+    }
+
+    async fn record_history(&self, track: &Track) {
+        let mut history = self.history.lock().await;
+        history.push_back(HistoryEntry {
+            track: track.clone(),
+            played_at: SystemTime::now(),
+        });
+        while history.len() > MAX_HISTORY {
+            history.pop_front();
+        }
+    }
+
+    /// Most recently played tracks first
+    pub async fn history(&self, limit: usize) -> Vec<HistoryEntry> {
+        let history = self.history.lock().await;
+        history.iter().rev().take(limit).cloned().collect()
+    }
+
+    /// Removes and returns the most recently played track
+    pub async fn take_previous(&self) -> Option<Track> {
+        let mut history = self.history.lock().await;
+        history.pop_back().map(|entry| entry.track)
+    }
+
+    /// Hands back a track that played to the end, so the loop mode can requeue it
+    pub async fn finish(&self, track: Track) {
+        self.record_history(&track).await;
+        match self.loop_mode().await {
+            LoopMode::Off => {}
+            LoopMode::Track => self.push_front(track).await,
+            LoopMode::Queue => self.push(track).await,
+        }
+    }
+
+    /// Like `finish`, but a skipped track is never replayed right away
+    pub async fn finish_skipped(&self, track: Track) {
+        self.record_history(&track).await;
+        if self.loop_mode().await == LoopMode::Queue {
+            self.push(track).await;
+        }
+    }
+
+    /// Reverts `finish(finished)` and the `pop` of `next`, if one followed,
+    /// e.g. for an aborted crossfade. `mode` is the loop mode that was active
+    /// when `finish` ran.
+    pub async fn unfinish(&self, mode: LoopMode, finished: &Track, next: Option<Track>) {
+        self.history.lock().await.pop_back();
+        let mut queue = self.inner.lock().await;
+
+        // The copy `finish` requeued, unless it was popped as `next`
+        let requeued = match mode {
+            LoopMode::Off => None,
+            LoopMode::Track => queue.front().filter(|t| t.id == finished.id).map(|_| 0),
+            LoopMode::Queue => queue
+                .back()
+                .filter(|t| t.id == finished.id)
+                .map(|_| queue.len() - 1),
+        };
+        if let Some(index) = requeued {
+            queue.remove(index);
+        }
+
+        if let Some(next) = next {
+            let next_was_requeued =
+                mode != LoopMode::Off && requeued.is_none() && next.id == finished.id;
+            if !next_was_requeued {
+                queue.push_front(next);
+            }
+        }
     }
 }
 
 pub struct AudioPlayer {
-    conn: VoiceConnection,
+    conn: SharedVoiceConnection,
     session: Option<Arc<VoiceSession>>,
     queue: Arc<TrackQueue>,
     seq: Arc<AtomicU16>,
     timestamp: Arc<AtomicU32>,
     is_playing: Arc<Mutex<bool>>,
+    queue_task: Mutex<Option<JoinHandle<()>>>,
     pub audio_filter_state: Arc<RwLock<AudioFilterState>>,
+    pub playback_position: SharedPlaybackPosition,
     pub filter_cmd_tx: mpsc::Sender<AudioCommand>,
     filter_cmd_rx: Arc<Mutex<Option<mpsc::Receiver<AudioCommand>>>>,
     filters: SharedAudioFilters,
     pub playback_cmd_tx: mpsc::Sender<AudioCommand>,
     playback_cmd_rx: Arc<Mutex<Option<mpsc::Receiver<AudioCommand>>>>,
+    received_audio_tx: broadcast::Sender<ReceivedAudio>,
+    events_tx: broadcast::Sender<TrackEvent>,
+    receiver_task: Mutex<Option<JoinHandle<()>>>,
+    recording: Mutex<Option<Recording>>,
+    clip_buffer: SharedClipBuffer,
+    clip_task: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl AudioPlayer {
-    pub fn new(conn: VoiceConnection, session: VoiceSession) -> Arc<Self> {
+    pub fn new(conn: SharedVoiceConnection, session: VoiceSession) -> Arc<Self> {
         let (cmd_tx, cmd_rx) = mpsc::channel::<AudioCommand>(8);
         let (p_cmd_tx, p_cmd_rx) = mpsc::channel::<AudioCommand>(8);
 
         let filter_state = Arc::new(RwLock::new(AudioFilterState::default()));
 
+        let (received_audio_tx, _) = broadcast::channel(RECEIVE_BUFFER_FRAMES);
+        let receiver_task = {
+            let conn = conn.clone();
+            let speakers = session.speakers();
+            let tx = received_audio_tx.clone();
+            tokio::spawn(async move {
+                if let Err(e) = audio_receiver(conn, speakers, tx).await {
+                    eprintln!("[AudioPlayer] Voice receive failed: {e:?}");
+                }
+            })
+        };
+
+        let clip_buffer = Arc::new(Mutex::new(ClipBuffer::new(CLIP_SECONDS)));
+        let clip_task = {
+            let clip_buffer = clip_buffer.clone();
+            let mut voice_rx = received_audio_tx.subscribe();
+            tokio::spawn(async move {
+                loop {
+                    match voice_rx.recv().await {
+                        Ok(audio) => clip_buffer.lock().await.push_voice(&audio),
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            })
+        };
+
         Arc::new(Self {
             conn,
             session: Some(Arc::new(session)),
@@ -111,12 +407,20 @@ impl AudioPlayer {
             seq: Arc::new(AtomicU16::new(0)),
             timestamp: Arc::new(AtomicU32::new(0)),
             is_playing: Arc::new(Mutex::new(false)),
+            queue_task: Mutex::new(None),
             audio_filter_state: filter_state,
+            playback_position: Arc::new(RwLock::new(PlaybackPosition::default())),
             filter_cmd_tx: cmd_tx,
             filter_cmd_rx: Arc::new(Mutex::new(Some(cmd_rx))),
             playback_cmd_tx: p_cmd_tx,
             playback_cmd_rx: Arc::new(Mutex::new(Some(p_cmd_rx))),
             filters: Arc::new(Mutex::new(AudioFilters::new(48_000.0))),
+            received_audio_tx,
+            events_tx: broadcast::channel(EVENT_BUFFER).0,
+            receiver_task: Mutex::new(Some(receiver_task)),
+            recording: Mutex::new(None),
+            clip_buffer,
+            clip_task: Mutex::new(Some(clip_task)),
         })
     }
 
@@ -131,6 +435,23 @@ impl AudioPlayer {
         }
         println!("[ENQUEUE] Track added: {}", track.title);
 
+        self.start_processing().await;
+    }
+
+    /// Puts tracks at the front of the queue, keeping their order
+    pub async fn enqueue_next(self: Arc<Self>, tracks: Vec<Track>) {
+        {
+            let mut q = self.queue.inner.lock().await;
+            for track in tracks.into_iter().rev() {
+                println!("[ENQUEUE] Track added next: {}", track.title);
+                q.push_front(track);
+            }
+        }
+
+        self.start_processing().await;
+    }
+
+    async fn start_processing(self: Arc<Self>) {
         let mut playing = self.is_playing.lock().await;
         if !*playing {
             *playing = true;
@@ -147,11 +468,12 @@ impl AudioPlayer {
 
             match (cmd_rx, playback_cmd_rx) {
                 (Some(cmd_rx), Some(playback_cmd_rx)) => {
-                    tokio::spawn(async move {
+                    let handle = tokio::spawn(async move {
                         if let Err(e) = player_clone.process_queue(cmd_rx, playback_cmd_rx).await {
                             eprintln!("[AudioPlayer] Queue processing failed: {e:?}");
                         }
                     });
+                    *self.queue_task.lock().await = Some(handle);
                 }
                 _ => println!("[ENQUEUE] Channels missing or already active"),
             }
@@ -159,14 +481,21 @@ impl AudioPlayer {
     }
 
     async fn process_queue(self: Arc<Self>, cmd_rx: mpsc::Receiver<AudioCommand>, playback_cmd_rx: mpsc::Receiver<AudioCommand>) -> Result<()> {
-        let (tx, rx) = mpsc::channel::<AudioFrame>(BUFFER_FRAMES);
+        let (tx, rx) = mpsc::channel::<PlaybackFrame>(BUFFER_FRAMES);
 
         let q = self.queue.clone();
-        let conn = Arc::new(self.conn.clone());
+        let conn = self.conn.clone();
         let seq = self.seq.clone();
         let ts = self.timestamp.clone();
 
-        let prod = tokio::spawn(audio_producer(q, tx, playback_cmd_rx));
+        let prod = tokio::spawn(audio_producer(
+            q,
+            tx,
+            playback_cmd_rx,
+            self.audio_filter_state.clone(),
+            self.playback_position.clone(),
+            self.events_tx.clone(),
+        ));
         let cons = tokio::spawn(audio_consumer(
             conn,
             seq,
@@ -175,6 +504,7 @@ impl AudioPlayer {
             cmd_rx,
             self.audio_filter_state.clone(),
             self.filters.clone(),
+            self.clip_buffer.clone(),
         ));
 
         let join = tokio::try_join!(prod, cons)?;
@@ -182,10 +512,16 @@ impl AudioPlayer {
         let (filter_cmd_res, playback_cmd_res) = join;
 
         let filter_cmd_rx = filter_cmd_res?;
-        let playback_cmd_rx = playback_cmd_res?;
+        let mut playback_cmd_rx = playback_cmd_res?;
 
         self.filter_cmd_rx.lock().await.replace(filter_cmd_rx);
-        self.playback_cmd_rx.lock().await.replace(playback_cmd_rx);
+        {
+            let mut parked = self.playback_cmd_rx.lock().await;
+            // Commands the producer didn't get to were meant for the playback
+            // that just ended. Dropping them also answers a pending Previous.
+            while playback_cmd_rx.try_recv().is_ok() {}
+            parked.replace(playback_cmd_rx);
+        }
 
         {
             let mut playing = self.is_playing.lock().await;
@@ -194,21 +530,103 @@ impl AudioPlayer {
 
         Ok(())
     }
-}
 
-/*
-       // Bot-Speaking OFF
-       let stop_payload = json!({
-           "op": 5,
-           "d": { "speaking": 0, "delay": 0, "ssrc": self.conn.ssrc }
-       });
-       {
-           let mut w = self.conn.ws.lock().await;
-           w.send(Message::Text(stop_payload.to_string())).await?;
-       }
-
-       println!(
-           "🔇 Bot stopped speaking ({} ended)",
-           if is_stream { "stream" } else { "file" }
-       );
-*/
\ No newline at end of file
+    /// Decoded incoming voice, one 20 ms frame per speaker per message.
+    /// Frames are tagged with SSRC and (once known) the speaking user's ID.
+    pub fn subscribe_voice(&self) -> broadcast::Receiver<ReceivedAudio> {
+        self.received_audio_tx.subscribe()
+    }
+
+    /// Track lifecycle events (started, ended, crossfade, errors, queue finished)
+    pub fn subscribe_events(&self) -> broadcast::Receiver<TrackEvent> {
+        self.events_tx.subscribe()
+    }
+
+    /// Returns `false` if a recording is already running
+    pub async fn start_recording(&self, dir: PathBuf) -> Result<bool> {
+        let mut recording = self.recording.lock().await;
+        if recording.is_some() {
+            return Ok(false);
+        }
+        *recording = Some(Recording::start(self.subscribe_voice(), dir)?);
+        Ok(true)
+    }
+
+    /// Returns the written files, or `None` if nothing was being recorded
+    pub async fn stop_recording(&self) -> Result<Option<Vec<PathBuf>>> {
+        let recording = self.recording.lock().await.take();
+        match recording {
+            Some(r) => Ok(Some(r.stop().await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Encodes the last `seconds` of channel audio (voice + music) as Ogg/Opus
+    pub async fn clip(&self, seconds: u64) -> Result<Vec<u8>> {
+        let frames = self.clip_buffer.lock().await.snapshot(seconds);
+        tokio::task::spawn_blocking(move || encode_clip(&frames)).await?
+    }
+
+    /// Hands `cmd` to the running producer, `false` if there is none. The
+    /// parked receiver is locked so the producer can't stop in between
+    /// without the command being dropped.
+    pub async fn send_to_producer(&self, cmd: AudioCommand) -> bool {
+        let parked = self.playback_cmd_rx.lock().await;
+        if parked.is_some() {
+            return false;
+        }
+        self.playback_cmd_tx.try_send(cmd).is_ok()
+    }
+
+    pub fn latency(&self) -> Option<Duration> {
+        self.session.as_ref().and_then(|s| s.latency())
+    }
+
+    /// Stops playback and tears down the voice side of the connection.
+    /// The gateway side (op 4 with `channel_id: null`) is handled by `DiscordVoiceApi::leave`.
+    pub async fn disconnect(&self) -> Result<()> {
+        // Nothing drains the channel while idle, so it may be full. A running
+        // producer that can't take Stop is aborted below.
+        if *self.is_playing.lock().await {
+            let _ = self.playback_cmd_tx.try_send(AudioCommand::Stop);
+        }
+
+        if let Some(mut handle) = self.queue_task.lock().await.take() {
+            if timeout(Duration::from_secs(2), &mut handle).await.is_err() {
+                println!("[AudioPlayer] Queue did not stop in time, aborting");
+                handle.abort();
+            }
+        }
+
+        if let Err(e) = self.stop_recording().await {
+            eprintln!("[AudioPlayer] Could not finish recording: {e:?}");
+        }
+        if let Some(handle) = self.receiver_task.lock().await.take() {
+            handle.abort();
+        }
+        if let Some(handle) = self.clip_task.lock().await.take() {
+            handle.abort();
+        }
+
+        let conn = self.conn.read().await;
+        let mut seq = self.seq.load(Ordering::Relaxed);
+        let mut ts = self.timestamp.load(Ordering::Relaxed);
+        for _ in 0..5 {
+            send_opus_packet(&conn, &OPUS_SILENCE_FRAME, seq, ts).await?;
+            seq = seq.wrapping_add(1);
+            ts = ts.wrapping_add(960);
+        }
+        self.seq.store(seq, Ordering::Relaxed);
+        self.timestamp.store(ts, Ordering::Relaxed);
+
+        if let Some(session) = &self.session {
+            // Bot-Speaking OFF
+            session.set_speaking(conn.ssrc, 0).await?;
+            println!("🔇 Bot stopped speaking");
+
+            session.close().await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use crate::discord_voice_api::voice::decoder::{DecoderHandle, PcmReader, spawn_decoder};
+use crate::discord_voice_api::voice::player::{FRAME_SIZE, LoopMode, Track, TrackQueue};
+use crate::discord_voice_api::voice::resolver::{is_expired, resolve_stream_url};
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use tokio::io::AsyncReadExt;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, timeout};
+
+/// Upcoming queue entries kept warm
+pub const PREFETCH_AHEAD: usize = 2;
+/// PCM decoded ahead per warmed track (20 ms frames)
+const WARMUP_FRAMES: usize = 100;
+/// A track that can't produce audio within this is reported as failed
+const WARMUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Resolves the stream URL if needed and starts decoding at `seconds`
+pub async fn open_track(track: &mut Track, seconds: f64) -> Result<(DecoderHandle, PcmReader)> {
+    // Resolved lazily; looped and seeked tracks can outlive their stream URL
+    if track.url.as_deref().map_or(true, is_expired) {
+        println!("[PREFETCH] 🔗 Resolving stream URL: {}", track.title);
+        match resolve_stream_url(&track.page_url()).await {
+            Ok(url) => track.url = Some(url),
+            Err(e) => eprintln!("[PREFETCH] Could not resolve stream URL: {e}"),
+        }
+    }
+    let url = track.url.as_ref().ok_or_else(|| anyhow!("No stream URL for {}", track.title))?;
+    spawn_decoder(url, &track.page_url(), Duration::from_secs_f64(seconds)).await
+}
+
+/// A started decoder with its first seconds of PCM already read
+pub struct WarmStream {
+    pub url: Option<String>,
+    pub proc: DecoderHandle,
+    pub out: PcmReader,
+    /// s16le PCM read from `out` during warm-up, to be played first
+    pub head: Vec<u8>,
+}
+
+/// Opens the track and reads ahead; doubles as health check, since a
+/// decoder that exits without any audio would only play silence
+async fn warm_up(mut track: Track) -> Result<WarmStream> {
+    let (proc, mut out) = open_track(&mut track, 0.0).await?;
+
+    let mut head = vec![0u8; WARMUP_FRAMES * FRAME_SIZE];
+    let mut filled = 0;
+    let read = async {
+        while filled < head.len() {
+            let n = out.read(&mut head[filled..]).await?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    timeout(WARMUP_TIMEOUT, read)
+        .await
+        .map_err(|_| anyhow!("No audio after {}s", WARMUP_TIMEOUT.as_secs()))??;
+
+    if filled == 0 {
+        return Err(anyhow!("Decoder produced no audio"));
+    }
+    head.truncate(filled);
+
+    println!("[PREFETCH] ✅ Warmed up: {}", track.title);
+    Ok(WarmStream {
+        url: track.url,
+        proc,
+        out,
+        head,
+    })
+}
+
+pub enum Prefetched {
+    /// Popped from the queue, ready to play without waiting
+    Ready(Track, WarmStream),
+    /// Popped from the queue, but it can't be played
+    Failed(Track, anyhow::Error),
+    /// The next track is still warming up
+    Pending,
+    Empty,
+}
+
+/// Warms up upcoming tracks in the background, so the producer never
+/// waits for yt-dlp or ffmpeg between tracks
+pub struct Prefetcher {
+    /// Keyed by track id
+    jobs: HashMap<String, JoinHandle<Result<WarmStream>>>,
+}
+
+impl Prefetcher {
+    pub fn new() -> Self {
+        Self {
+            jobs: HashMap::new(),
+        }
+    }
+
+    fn start(&mut self, track: &Track) {
+        if self.jobs.contains_key(&track.id) {
+            return;
+        }
+        println!("[PREFETCH] Warming up: {}", track.title);
+        self.jobs.insert(track.id.clone(), tokio::spawn(warm_up(track.clone())));
+    }
+
+    /// Starts warming up the tracks that play next and drops the ones
+    /// that were removed, moved back or skipped in the meantime
+    pub async fn refresh(&mut self, queue: &TrackQueue) {
+        let mut upcoming = Vec::with_capacity(PREFETCH_AHEAD);
+        // The current track is requeued at its end, so it plays next
+        if queue.loop_mode().await == LoopMode::Track {
+            upcoming.extend(queue.get_current_track().await);
+        }
+        let remaining = PREFETCH_AHEAD.saturating_sub(upcoming.len());
+        upcoming.extend(queue.peek(remaining).await);
+
+        self.jobs.retain(|id, job| {
+            let keep = upcoming.iter().any(|t| &t.id == id);
+            if !keep {
+                job.abort();
+            }
+            keep
+        });
+        for track in &upcoming {
+            self.start(track);
+        }
+    }
+
+    /// Pops the next track once its warm-up is done. Never waits for it.
+    pub async fn take_next(&mut self, queue: &TrackQueue) -> Prefetched {
+        let Some(next) = queue.peek(1).await.pop() else {
+            return Prefetched::Empty;
+        };
+        self.start(&next);
+        if !self.jobs.get(&next.id).is_some_and(|job| job.is_finished()) {
+            return Prefetched::Pending;
+        }
+
+        let Some(mut track) = queue.pop_if(&next.id).await else {
+            // The queue changed in between
+            return Prefetched::Pending;
+        };
+        let job = self.jobs.remove(&next.id).expect("checked above");
+        match job.await {
+            Ok(Ok(stream)) => {
+                track.url = stream.url.clone();
+                Prefetched::Ready(track, stream)
+            }
+            Ok(Err(e)) => Prefetched::Failed(track, e),
+            Err(e) => Prefetched::Failed(track, anyhow!("Warm-up task failed: {e}")),
+        }
+    }
+}
+
+impl Drop for Prefetcher {
+    fn drop(&mut self) {
+        // Finished jobs drop their decoder along with the handle
+        for job in self.jobs.values() {
+            job.abort();
+        }
+    }
+}
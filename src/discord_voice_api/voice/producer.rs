@@ -1,29 +1,58 @@
-use crate::discord_voice_api::voice::ffmpeg::spawn_ffmpeg_with_buffer;
+use crate::discord_voice_api::voice::decoder::{DecoderHandle, PcmReader};
+use crate::discord_voice_api::voice::events::TrackEvent;
 use crate::discord_voice_api::voice::player::{
-    AudioFrame, FADE_SEC, FRAME_SIZE, Track, TrackQueue,
+    FADE_SEC, FRAME_SIZE, LoopMode, PlaybackFrame, PlaybackPosition, SharedPlaybackPosition,
+    Track, TrackQueue,
 };
+use crate::discord_voice_api::voice::prefetch::{Prefetched, Prefetcher, open_track};
 use anyhow::Result;
 use std::sync::Arc;
 use tokio::io::AsyncReadExt;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use tokio::time::Duration;
-use crate::discord_voice_api::voice::audio_commands::AudioCommand;
+use crate::discord_voice_api::voice::audio_commands::{
+    AudioCommand, AudioFilterState, SharedAudioFilterState,
+};
+use crate::discord_voice_api::voice::resampler::Resampler;
+
+pub const NIGHTCORE_RATE: f64 = 1.25;
+pub const VAPORWAVE_RATE: f64 = 0.8;
+
+/// Playback speed of the source material (pitch and tempo)
+fn playback_rate(state: &AudioFilterState) -> f64 {
+    if state.nightcore {
+        NIGHTCORE_RATE
+    } else if state.vaporwave {
+        VAPORWAVE_RATE
+    } else {
+        1.0
+    }
+}
 
 pub async fn audio_producer(
     queue: Arc<TrackQueue>,
-    tx: mpsc::Sender<AudioFrame>,
-    mut playback_cmd_rx: mpsc::Receiver<AudioCommand>
+    tx: mpsc::Sender<PlaybackFrame>,
+    mut playback_cmd_rx: mpsc::Receiver<AudioCommand>,
+    filter_state: SharedAudioFilterState,
+    position: SharedPlaybackPosition,
+    events: broadcast::Sender<TrackEvent>,
 ) -> Result<(mpsc::Receiver<AudioCommand>)> {
-    let mut current_proc: Option<tokio::process::Child> = None;
-    let mut current_out: Option<tokio::process::ChildStdout> = None;
+    let mut current_proc: Option<DecoderHandle> = None;
+    let mut current_out: Option<PcmReader> = None;
     let mut current_track: Option<Track> = None;
     let mut played_seconds: f64 = 0.0;
     let mut paused = false;
+    // Set once the loop mode has handled the current track (at the crossfade point)
+    let mut current_finished = false;
 
     struct CrossfadeState {
-        proc: Option<tokio::process::Child>,
-        out: Option<tokio::process::ChildStdout>,
+        proc: Option<DecoderHandle>,
+        out: Option<PcmReader>,
         track: Option<Track>,
+        resampler: Resampler,
+        loop_mode: LoopMode,
+        /// Seconds of the next track already faded in
+        played: f64,
         fading: bool,
     }
 
@@ -31,16 +60,41 @@ pub async fn audio_producer(
         proc: None,
         out: None,
         track: None,
+        resampler: Resampler::new(),
+        loop_mode: LoopMode::Off,
+        played: 0.0,
         fading: false,
     };
+    let mut resampler_curr = Resampler::new();
+    let mut prefetcher = Prefetcher::new();
 
     let mut buf_curr = vec![0u8; FRAME_SIZE];
     let mut buf_next = vec![0u8; FRAME_SIZE];
     let frame_duration = 960.0 / 48000.0;
 
-    async fn start_track(track: &Track) -> Result<(tokio::process::Child, tokio::process::ChildStdout)> {
-        let url = track.url.as_ref().unwrap();
-        spawn_ffmpeg_with_buffer(url, 64).await
+    /// Undoes the early `finish` at the crossfade point: stops fading into
+    /// the next track and puts it back into the queue
+    async fn reopen_current(
+        crossfade: &mut CrossfadeState,
+        current_finished: &mut bool,
+        current: Option<&Track>,
+        queue: &TrackQueue,
+    ) {
+        if !*current_finished {
+            return;
+        }
+        // Without a current track it already ended and stays finished
+        let Some(current) = current else {
+            return;
+        };
+        if let Some(mut proc) = crossfade.proc.take() {
+            let _ = proc.kill().await;
+        }
+        crossfade.out = None;
+        queue.unfinish(crossfade.loop_mode, current, crossfade.track.take()).await;
+        crossfade.resampler.clear();
+        crossfade.fading = false;
+        *current_finished = false;
     }
 
     loop {
@@ -48,21 +102,134 @@ pub async fn audio_producer(
             match cmd {
                 AudioCommand::Pause => {
                     paused = true;
+                    position.write().await.paused = true;
                     println!("[PRODUCER] ⏸ Paused");
                 }
                 AudioCommand::Resume => {
                     paused = false;
+                    position.write().await.paused = false;
                     println!("[PRODUCER] ▶ Resumed");
                 }
                 AudioCommand::Skip => {
                     println!("[PRODUCER] ⏭ Skipping track");
+                    // Track loop requeued the current track at the crossfade point,
+                    // a skipped track must not play again
+                    if crossfade.loop_mode == LoopMode::Track {
+                        reopen_current(
+                            &mut crossfade,
+                            &mut current_finished,
+                            current_track.as_ref(),
+                            &queue,
+                        )
+                        .await;
+                    }
                     if let Some(mut proc) = current_proc.take() {
                         let _ = proc.kill().await;
                     }
                     current_out = None;
-                    current_track = None;
+                    if let Some(track) = current_track.take() {
+                        if !current_finished {
+                            queue.finish_skipped(track.clone()).await;
+                        }
+                        let _ = events.send(TrackEvent::Ended(track));
+                    }
+                    resampler_curr.clear();
+
+                    if crossfade.fading {
+                        // Jump straight to the track being faded in
+                        current_proc = crossfade.proc.take();
+                        current_out = crossfade.out.take();
+                        current_track = crossfade.track.take();
+                        if let Some(track) = current_track.as_ref() {
+                            queue.set_current_track(track.clone()).await;
+                            let _ = events.send(TrackEvent::Started(track.clone()));
+                        }
+                        played_seconds = crossfade.played;
+                        current_finished = false;
+                        crossfade.fading = false;
+                        std::mem::swap(&mut resampler_curr, &mut crossfade.resampler);
+                        crossfade.resampler.clear();
+                    }
                     continue;
                 }
+                AudioCommand::Stop => {
+                    println!("[PRODUCER] ⏹ Stopping playback");
+                    if let Some(mut proc) = current_proc.take() {
+                        let _ = proc.kill().await;
+                    }
+                    if let Some(mut proc) = crossfade.proc.take() {
+                        let _ = proc.kill().await;
+                    }
+                    queue.clear_current_track().await;
+                    *position.write().await = PlaybackPosition::default();
+                    return Ok(playback_cmd_rx);
+                }
+                AudioCommand::Previous(reply) => {
+                    // Past the crossfade point the newest history entry is the current track itself
+                    reopen_current(
+                        &mut crossfade,
+                        &mut current_finished,
+                        current_track.as_ref(),
+                        &queue,
+                    )
+                    .await;
+                    let Some(previous) = queue.take_previous().await else {
+                        let _ = reply.send(None);
+                        continue;
+                    };
+                    println!("[PRODUCER] ⏮ Going back to: {}", previous.title);
+                    let _ = reply.send(Some(previous.clone()));
+                    if let Some(mut proc) = current_proc.take() {
+                        let _ = proc.kill().await;
+                    }
+                    current_out = None;
+                    if let Some(track) = current_track.take() {
+                        queue.push_front(track.clone()).await;
+                        let _ = events.send(TrackEvent::Ended(track));
+                    }
+                    queue.push_front(previous).await;
+                    resampler_curr.clear();
+                }
+                AudioCommand::Seek(target) => {
+                    let Some(mut track) = current_track.clone() else {
+                        continue;
+                    };
+                    let mut seconds = target.as_secs_f64();
+                    if let Some(total_dur) = track.known_duration() {
+                        seconds = seconds.min(total_dur);
+                    }
+                    println!("[PRODUCER] ⏩ Seeking to {:.1}s", seconds);
+
+                    // A crossfade in progress belongs to the old position
+                    reopen_current(
+                        &mut crossfade,
+                        &mut current_finished,
+                        Some(&track),
+                        &queue,
+                    )
+                    .await;
+
+                    if let Some(mut proc) = current_proc.take() {
+                        let _ = proc.kill().await;
+                    }
+                    resampler_curr.clear();
+                    match open_track(&mut track, seconds).await {
+                        Ok((proc, out)) => {
+                            current_proc = Some(proc);
+                            current_out = Some(out);
+                            current_track = Some(track);
+                            // Crossfade timing is derived from this, so it stays correct
+                            played_seconds = seconds;
+                            position.write().await.seconds = played_seconds;
+                        }
+                        Err(e) => {
+                            eprintln!("[PRODUCER] ❌ Could not seek in {}: {e:?}", track.title);
+                            current_out = None;
+                            current_track = None;
+                            let _ = events.send(TrackEvent::Errored { track, error: e.to_string() });
+                        }
+                    }
+                }
                 _ => {}
             }
         }
@@ -74,107 +241,204 @@ pub async fn audio_producer(
 
         if current_out.is_none() {
             played_seconds = 0.0;
-            if let Some(track) = queue.pop().await {
-                queue.set_current_track(track.clone()).await;
-                println!("[PRODUCER] ▶ Starting track: {}", track.title);
-                let (proc, out) = start_track(&track).await?;
-                current_proc = Some(proc);
-                current_out = Some(out);
-                current_track = Some(track);
-            } else {
-                println!("[PRODUCER] ✅ Queue finished.");
-                queue.clear_current_track().await;
-                break;
+            match prefetcher.take_next(&queue).await {
+                Prefetched::Ready(track, stream) => {
+                    println!("[PRODUCER] ▶ Starting track: {}", track.title);
+                    queue.set_current_track(track.clone()).await;
+                    let _ = events.send(TrackEvent::Started(track.clone()));
+                    current_proc = Some(stream.proc);
+                    current_out = Some(stream.out);
+                    current_track = Some(track);
+                    current_finished = false;
+                    resampler_curr.clear();
+                    resampler_curr.push_bytes(&stream.head);
+                }
+                Prefetched::Failed(track, e) => {
+                    eprintln!("[PRODUCER] ❌ Could not start {}: {e:?}", track.title);
+                    let _ = events.send(TrackEvent::Errored { track, error: e.to_string() });
+                    continue;
+                }
+                Prefetched::Pending => {
+                    // Keeps handling commands while the next track warms up
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    continue;
+                }
+                Prefetched::Empty => {
+                    println!("[PRODUCER] ✅ Queue finished.");
+                    queue.clear_current_track().await;
+                    *position.write().await = PlaybackPosition::default();
+                    let _ = events.send(TrackEvent::QueueFinished);
+                    break;
+                }
             }
         }
 
-        let n = match current_out.as_mut() {
-            Some(out) => out.read(&mut buf_curr).await.unwrap_or(0),
-            None => continue,
-        };
+        prefetcher.refresh(&queue).await;
+
+        let rate = playback_rate(&*filter_state.read().await);
+
+        // Read until the resampler has a full frame at the current rate. Back
+        // at 1.0 after a rate change, frames first snap back onto the source's
+        // packets so passthrough can take over again.
+        let mut aligned = rate != 1.0 || resampler_curr.align();
+        let mut frame_start = None;
+        let mut frame_curr = None;
+        loop {
+            if !aligned {
+                aligned = resampler_curr.align();
+            }
+            if aligned {
+                frame_start = resampler_curr.position();
+                frame_curr = resampler_curr.next_frame(rate);
+                if frame_curr.is_some() {
+                    break;
+                }
+            }
+            let n = match current_out.as_mut() {
+                Some(out) => out.read(&mut buf_curr).await.unwrap_or(0),
+                None => 0,
+            };
+            if n == 0 {
+                break;
+            }
+            resampler_curr.push_bytes(&buf_curr[..n]);
+        }
 
-        let ffmpeg_alive = current_proc.as_mut()
-            .map(|p| p.try_wait().map(|s| s.is_none()).unwrap_or(true))
+        let decoder_alive = current_proc.as_mut()
+            .map(|p| p.is_running())
             .unwrap_or(false);
 
-        if n == 0 && !ffmpeg_alive && !crossfade.fading {
+        if frame_curr.is_none() && !decoder_alive && !crossfade.fading {
             println!(
                 "[PRODUCER] ⏹ Track ended: {}",
                 current_track.as_ref().map(|t| &t.title).unwrap_or(&"<unknown>".to_string())
             );
             current_proc = None;
             current_out = None;
-            current_track = None;
+            if let Some(track) = current_track.take() {
+                if !current_finished {
+                    queue.finish(track.clone()).await;
+                }
+                let _ = events.send(TrackEvent::Ended(track));
+            }
             continue;
         }
 
-        if n == 0 && ffmpeg_alive && !crossfade.fading {
+        if frame_curr.is_none() && decoder_alive && !crossfade.fading {
             tokio::time::sleep(Duration::from_millis(50)).await;
             continue;
         }
 
-        let pcm_curr: Vec<i16> = buf_curr.chunks_exact(2)
-            .map(|b| i16::from_le_bytes([b[0], b[1]]))
-            .collect();
+        let decoded = frame_curr.is_some();
+        let pcm_curr: Vec<i16> = frame_curr.unwrap_or_else(|| vec![0; FRAME_SIZE / 2]);
 
-        played_seconds += frame_duration;
+        // Track time advances faster (or slower) than wall time when resampling
+        played_seconds += frame_duration * rate;
 
-        if !crossfade.fading {
+        if !current_finished {
             if let Some(track) = current_track.as_ref() {
-                if let Some(total_dur) = track.duration {
-                    if total_dur - played_seconds <= FADE_SEC {
-                        if let Some(next_track) = queue.pop().await {
+                // Unknown-length tracks play until the stream ends, without crossfade
+                if let Some(total_dur) = track.known_duration() {
+                    if (total_dur - played_seconds) / rate <= FADE_SEC {
+                        crossfade.loop_mode = queue.loop_mode().await;
+                        queue.finish(track.clone()).await;
+                        current_finished = true;
+                    }
+                }
+            }
+        }
+
+        // Fades in the next track as soon as it is warmed up. A late start
+        // only shortens the fade, the frame loop never waits for it.
+        if current_finished && !crossfade.fading {
+            loop {
+                match prefetcher.take_next(&queue).await {
+                    Prefetched::Ready(next_track, stream) => {
+                        if let Some(track) = current_track.as_ref() {
                             println!(
                                 "[PRODUCER] 🔁 Initiating crossfade: {} → {}",
                                 track.title, next_track.title
                             );
-                            let (proc, out) = start_track(&next_track).await?;
-                            crossfade.proc = Some(proc);
-                            crossfade.out = Some(out);
-                            crossfade.track = Some(next_track);
-                            crossfade.fading = true;
+                            let _ = events.send(TrackEvent::CrossfadeStarted {
+                                from: track.clone(),
+                                to: next_track.clone(),
+                            });
                         }
+                        crossfade.proc = Some(stream.proc);
+                        crossfade.out = Some(stream.out);
+                        crossfade.track = Some(next_track);
+                        crossfade.resampler.clear();
+                        crossfade.resampler.push_bytes(&stream.head);
+                        crossfade.played = 0.0;
+                        crossfade.fading = true;
+                        break;
                     }
+                    Prefetched::Failed(next_track, e) => {
+                        eprintln!("[PRODUCER] ❌ Could not start {}: {e:?}", next_track.title);
+                        let _ = events.send(TrackEvent::Errored {
+                            track: next_track,
+                            error: e.to_string(),
+                        });
+                    }
+                    Prefetched::Pending | Prefetched::Empty => break,
                 }
             }
         }
 
+        let mixing = crossfade.fading;
         let frame: Vec<i16> = if crossfade.fading {
-            if let Some(no) = crossfade.out.as_mut() {
-                if no.read_exact(&mut buf_next).await.is_ok() {
-                    let pcm_next: Vec<i16> = buf_next.chunks_exact(2)
-                        .map(|b| i16::from_le_bytes([b[0], b[1]]))
-                        .collect();
-
-                    let total_dur = current_track.as_ref()
-                        .and_then(|t| t.duration)
-                        .unwrap_or(FADE_SEC);
-
-                    let fade_pos = ((FADE_SEC - (total_dur - played_seconds)) / FADE_SEC)
-                        .clamp(0.0, 1.0);
-
-                    let mixed: Vec<i16> = pcm_curr.iter()
-                        .zip(pcm_next.iter())
-                        .map(|(&a, &b)| ((a as f64 * (1.0 - fade_pos)) + (b as f64 * fade_pos)) as i16)
-                        .collect();
-
-                    if fade_pos >= 1.0 {
-                        println!("[PRODUCER] ✅ Crossfade completed.");
-                        if let Some(mut proc) = current_proc.take() {
-                            let _ = proc.kill().await;
-                        }
-                        current_proc = crossfade.proc.take();
-                        current_out = crossfade.out.take();
-                        current_track = crossfade.track.take();
-                        played_seconds = FADE_SEC;
-                        crossfade.fading = false;
-                        buf_next.fill(0);
-                    }
+            let mut frame_next = crossfade.resampler.next_frame(rate);
+            while frame_next.is_none() {
+                let n = match crossfade.out.as_mut() {
+                    Some(no) => no.read(&mut buf_next).await.unwrap_or(0),
+                    None => 0,
+                };
+                if n == 0 {
+                    break;
+                }
+                crossfade.resampler.push_bytes(&buf_next[..n]);
+                frame_next = crossfade.resampler.next_frame(rate);
+            }
+
+            if let Some(pcm_next) = frame_next {
+                crossfade.played += frame_duration * rate;
+
+                let total_dur = current_track.as_ref()
+                    .and_then(|t| t.known_duration())
+                    .unwrap_or(FADE_SEC);
+
+                // Remaining time of the current track in wall-clock seconds
+                let remaining = (total_dur - played_seconds) / rate;
+                let fade_pos = ((FADE_SEC - remaining) / FADE_SEC).clamp(0.0, 1.0);
+
+                let mixed: Vec<i16> = pcm_curr.iter()
+                    .zip(pcm_next.iter())
+                    .map(|(&a, &b)| ((a as f64 * (1.0 - fade_pos)) + (b as f64 * fade_pos)) as i16)
+                    .collect();
 
-                    mixed
-                } else {
-                    pcm_curr
+                if fade_pos >= 1.0 {
+                    println!("[PRODUCER] ✅ Crossfade completed.");
+                    if let Some(mut proc) = current_proc.take() {
+                        let _ = proc.kill().await;
+                    }
+                    if let Some(track) = current_track.take() {
+                        let _ = events.send(TrackEvent::Ended(track));
+                    }
+                    current_proc = crossfade.proc.take();
+                    current_out = crossfade.out.take();
+                    current_track = crossfade.track.take();
+                    if let Some(track) = current_track.as_ref() {
+                        queue.set_current_track(track.clone()).await;
+                        let _ = events.send(TrackEvent::Started(track.clone()));
+                    }
+                    played_seconds = crossfade.played;
+                    current_finished = false;
+                    crossfade.fading = false;
+                    std::mem::swap(&mut resampler_curr, &mut crossfade.resampler);
+                    crossfade.resampler.clear();
                 }
+
+                mixed
             } else {
                 pcm_curr
             }
@@ -182,11 +446,27 @@ pub async fn audio_producer(
             pcm_curr
         };
 
-        if tx.send(frame).await.is_err() {
+        {
+            let mut pos = position.write().await;
+            pos.seconds = played_seconds;
+            pos.crossfading_into = crossfade.track.as_ref().map(|t| t.title.clone());
+        }
+
+        // A verbatim copy of the source can go out as the source's own packet
+        let opus = match (frame_start, current_proc.as_mut()) {
+            // Also asked when the packet can't be used, which drops the ones
+            // already played instead of letting them fill the buffer
+            (Some(start), Some(decoder)) => decoder
+                .opus_packet_at(start)
+                .filter(|_| decoded && rate == 1.0 && !mixing),
+            _ => None,
+        };
+
+        if tx.send(PlaybackFrame { pcm: frame, opus }).await.is_err() {
             println!("[PRODUCER] Consumer disconnected");
             break;
         }
     }
 
-    Ok((playback_cmd_rx))
+    Ok(playback_cmd_rx)
 }
\ No newline at end of file
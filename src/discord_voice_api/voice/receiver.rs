@@ -0,0 +1,284 @@
+use crate::discord_voice_api::voice::connection::SharedVoiceConnection;
+use crate::discord_voice_api::voice::crypto::CipherMode;
+use crate::discord_voice_api::voice::ogg_writer::SAMPLES_PER_FRAME;
+use crate::discord_voice_api::voice::player::AudioFrame;
+use anyhow::Result;
+use opus::{Channels, Decoder};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::sync::Arc;
+use tokio::sync::{RwLock, broadcast};
+use tokio::time::{Duration, timeout};
+
+/// SSRC → Discord user ID, filled from voice op 5 SPEAKING events
+pub type SpeakerMap = Arc<RwLock<HashMap<u32, String>>>;
+
+const OPUS_PAYLOAD_TYPE: u8 = 0x78;
+const MAX_FRAME_SAMPLES: usize = 5760; // 120 ms @ 48 kHz, per channel
+
+/// One decoded 20 ms frame from a single speaker
+#[derive(Clone, Debug)]
+pub struct ReceivedAudio {
+    pub ssrc: u32,
+    pub user_id: Option<String>,
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub pcm: AudioFrame, // s16le 48 kHz stereo, interleaved
+}
+
+/// A speaker whose RTP clock is further off than this many frames (client
+/// restarted, timestamp reset) is anchored again at the arrival frame
+const MAX_CLOCK_DRIFT: u64 = 50;
+
+/// Maps one speaker's RTP timestamps onto a local 20 ms frame clock,
+/// anchored at their first packet. Arrival times jitter, RTP timestamps don't.
+#[derive(Clone, Copy, Debug)]
+pub struct RtpClock {
+    first_rtp_ts: u32,
+    first_frame: u64,
+}
+
+impl RtpClock {
+    pub fn new(rtp_ts: u32, frame: u64) -> Self {
+        Self {
+            first_rtp_ts: rtp_ts,
+            first_frame: frame,
+        }
+    }
+
+    /// `None` for a packet from before the first one: the RTP delta is
+    /// negative then and wraps to well past 2^31 samples.
+    pub fn frame_index(&self, rtp_ts: u32) -> Option<u64> {
+        let delta = rtp_ts.wrapping_sub(self.first_rtp_ts);
+        if delta > i32::MAX as u32 {
+            return None;
+        }
+        Some(self.first_frame + delta as u64 / SAMPLES_PER_FRAME)
+    }
+
+    /// How far `rtp_ts` lies before the first packet, in samples
+    pub fn samples_before(&self, rtp_ts: u32) -> u64 {
+        self.first_rtp_ts.wrapping_sub(rtp_ts) as u64
+    }
+
+    /// Frame for a packet that arrived at local frame `now`, `None` for a
+    /// late packet from just before the first one. Re-anchors at `now` when
+    /// the clock drifted more than `MAX_CLOCK_DRIFT` from arrival times.
+    pub fn place(&mut self, rtp_ts: u32, now: u64) -> Option<u64> {
+        match self.frame_index(rtp_ts) {
+            Some(frame) if frame.abs_diff(now) <= MAX_CLOCK_DRIFT => Some(frame),
+            None if self.samples_before(rtp_ts) <= MAX_CLOCK_DRIFT * SAMPLES_PER_FRAME => None,
+            _ => {
+                *self = RtpClock::new(rtp_ts, now);
+                Some(now)
+            }
+        }
+    }
+}
+
+struct RtpHeader {
+    sequence: u16,
+    timestamp: u32,
+    ssrc: u32,
+    /// Fixed header + CSRCs + extension header (the AAD in the `_rtpsize` modes)
+    header_len: usize,
+    /// Length of the (encrypted) extension body in front of the Opus payload
+    extension_len: usize,
+}
+
+fn parse_rtp_header(packet: &[u8]) -> Option<RtpHeader> {
+    if packet.len() < 12 || packet[0] >> 6 != 2 {
+        return None;
+    }
+
+    // RTCP shares the socket; only Opus RTP is interesting here
+    if packet[1] & 0x7F != OPUS_PAYLOAD_TYPE {
+        return None;
+    }
+
+    let has_extension = packet[0] & 0x10 != 0;
+    let csrc_count = (packet[0] & 0x0F) as usize;
+
+    let mut header_len = 12 + 4 * csrc_count;
+    let mut extension_len = 0;
+    if has_extension {
+        if packet.len() < header_len + 4 {
+            return None;
+        }
+        let words = u16::from_be_bytes([packet[header_len + 2], packet[header_len + 3]]);
+        extension_len = words as usize * 4;
+        header_len += 4;
+    }
+
+    Some(RtpHeader {
+        sequence: u16::from_be_bytes([packet[2], packet[3]]),
+        timestamp: u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+        ssrc: u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]),
+        header_len,
+        extension_len,
+    })
+}
+
+/// Decrypts one RTP packet and returns the header plus the bare Opus payload
+fn open_packet(cipher: &CipherMode, packet: &[u8]) -> Option<(RtpHeader, Vec<u8>)> {
+    let header = parse_rtp_header(packet)?;
+    if packet.len() < header.header_len + 4 {
+        return None;
+    }
+
+    let nonce_start = packet.len() - 4;
+    let counter = u32::from_be_bytes(packet[nonce_start..].try_into().ok()?);
+    let plaintext = cipher
+        .decrypt_packet(
+            &packet[..header.header_len],
+            &packet[header.header_len..nonce_start],
+            counter,
+        )
+        .ok()?;
+
+    if plaintext.len() < header.extension_len {
+        return None;
+    }
+    let opus = plaintext[header.extension_len..].to_vec();
+    Some((header, opus))
+}
+
+pub async fn audio_receiver(
+    conn: SharedVoiceConnection,
+    speakers: SpeakerMap,
+    tx: broadcast::Sender<ReceivedAudio>,
+) -> Result<()> {
+    let mut decoders: HashMap<u32, Decoder> = HashMap::new();
+    let mut buf = [0u8; 1500];
+    let mut pcm = vec![0i16; MAX_FRAME_SAMPLES * 2];
+
+    println!("[RECEIVER] Listening for incoming voice");
+
+    loop {
+        // Re-read every round so a reconnect swaps us over to the new socket
+        let (socket, cipher, own_ssrc) = {
+            let c = conn.read().await;
+            (c.socket.clone(), c.cipher.clone(), c.ssrc)
+        };
+
+        let n = match timeout(Duration::from_millis(500), socket.recv(&mut buf)).await {
+            Ok(Ok(n)) => n,
+            Ok(Err(e)) => {
+                eprintln!("[RECEIVER] UDP error: {e}");
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                continue;
+            }
+            Err(_) => continue,
+        };
+
+        if tx.receiver_count() == 0 {
+            continue;
+        }
+
+        let Some((header, opus_payload)) = open_packet(&cipher, &buf[..n]) else {
+            continue;
+        };
+        if header.ssrc == own_ssrc {
+            continue;
+        }
+
+        let decoder = match decoders.entry(header.ssrc) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(Decoder::new(48000, Channels::Stereo)?),
+        };
+
+        let samples = match decoder.decode(&opus_payload, &mut pcm, false) {
+            Ok(samples) => samples,
+            Err(e) => {
+                eprintln!("[RECEIVER] Opus decode failed for ssrc {}: {e}", header.ssrc);
+                continue;
+            }
+        };
+
+        let user_id = speakers.read().await.get(&header.ssrc).cloned();
+        let _ = tx.send(ReceivedAudio {
+            ssrc: header.ssrc,
+            user_id,
+            sequence: header.sequence,
+            timestamp: header.timestamp,
+            pcm: pcm[..samples * 2].to_vec(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+    const MODES: [&str; 2] = ["aead_aes256_gcm_rtpsize", "aead_xchacha20_poly1305_rtpsize"];
+
+    /// RTP header as Discord sends it, optionally with a one-word extension
+    fn rtp_header(extension: bool) -> Vec<u8> {
+        let mut header = vec![if extension { 0x90 } else { 0x80 }, OPUS_PAYLOAD_TYPE];
+        header.extend_from_slice(&513u16.to_be_bytes());
+        header.extend_from_slice(&96000u32.to_be_bytes());
+        header.extend_from_slice(&4242u32.to_be_bytes());
+        if extension {
+            header.extend_from_slice(&[0xBE, 0xDE, 0x00, 0x01]);
+        }
+        header
+    }
+
+    fn seal(cipher: &CipherMode, header: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut packet = header.to_vec();
+        packet.extend(cipher.encrypt_packet(header, body, 99).unwrap());
+        packet
+    }
+
+    #[test]
+    fn opens_plain_packets() {
+        let opus = [0xF8, 0xFF, 0xFE];
+        for mode in MODES {
+            let cipher = CipherMode::from_secret_and_mode(&KEY, mode).unwrap();
+            let packet = seal(&cipher, &rtp_header(false), &opus);
+
+            let (header, payload) = open_packet(&cipher, &packet).expect(mode);
+            assert_eq!(header.sequence, 513);
+            assert_eq!(header.timestamp, 96000);
+            assert_eq!(header.ssrc, 4242);
+            assert_eq!(payload, opus, "{mode}");
+        }
+    }
+
+    #[test]
+    fn strips_encrypted_extension_body() {
+        // The extension header is AAD, its body is encrypted with the payload
+        let opus = [1, 2, 3, 4, 5];
+        let mut body = vec![0x10, 0x22, 0x33, 0x44];
+        body.extend_from_slice(&opus);
+        for mode in MODES {
+            let cipher = CipherMode::from_secret_and_mode(&KEY, mode).unwrap();
+            let packet = seal(&cipher, &rtp_header(true), &body);
+
+            let (header, payload) = open_packet(&cipher, &packet).expect(mode);
+            assert_eq!(header.header_len, 16);
+            assert_eq!(header.extension_len, 4);
+            assert_eq!(payload, opus, "{mode}");
+        }
+    }
+
+    #[test]
+    fn rejects_tampered_header() {
+        for mode in MODES {
+            let cipher = CipherMode::from_secret_and_mode(&KEY, mode).unwrap();
+            let mut packet = seal(&cipher, &rtp_header(false), &[9, 9, 9]);
+            packet[3] ^= 1;
+            assert!(open_packet(&cipher, &packet).is_none(), "{mode}");
+        }
+    }
+
+    #[test]
+    fn ignores_rtcp() {
+        // Receiver report: version 2, packet type 201
+        let mut packet = vec![0x81, 201, 0x00, 0x07];
+        packet.extend_from_slice(&4242u32.to_be_bytes());
+        packet.resize(32, 0);
+        assert!(parse_rtp_header(&packet).is_none());
+    }
+}
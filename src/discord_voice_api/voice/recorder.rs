@@ -0,0 +1,192 @@
+use crate::discord_voice_api::voice::ogg_writer::{OggOpusWriter, SAMPLES_PER_FRAME};
+use crate::discord_voice_api::voice::receiver::{ReceivedAudio, RtpClock};
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+
+type FileWriter = OggOpusWriter<BufWriter<File>>;
+
+/// How long the mixed track waits for late packets before a frame is written
+const MIX_DELAY_FRAMES: u64 = 10;
+/// Messages queued for the writer thread (~2 s of four speakers)
+const WRITER_BUFFER: usize = 400;
+const FRAME_SAMPLES: usize = (SAMPLES_PER_FRAME * 2) as usize;
+
+/// One speaker's file. RTP timestamps are mapped onto the recording's
+/// frame clock so every file lines up with the mixed one.
+struct SpeakerTrack {
+    writer: FileWriter,
+    path: PathBuf,
+    clock: RtpClock,
+}
+
+pub struct Recording {
+    pub dir: PathBuf,
+    stop_tx: oneshot::Sender<()>,
+    task: JoinHandle<Result<Vec<PathBuf>>>,
+}
+
+impl Recording {
+    pub fn start(voice_rx: broadcast::Receiver<ReceivedAudio>, dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let task = tokio::spawn(record_loop(voice_rx, stop_rx, dir.clone()));
+
+        println!("[RECORDER] ⏺ Recording to {}", dir.display());
+        Ok(Self { dir, stop_tx, task })
+    }
+
+    /// Finalizes all files and returns their paths (mixed file first)
+    pub async fn stop(self) -> Result<Vec<PathBuf>> {
+        let _ = self.stop_tx.send(());
+        let files = self.task.await??;
+        println!("[RECORDER] ⏹ Recording finished ({} files)", files.len());
+        Ok(files)
+    }
+}
+
+/// What the receive loop hands the writer thread, stamped with the
+/// recording's frame clock at the time it happened
+enum WriterMsg {
+    Audio(ReceivedAudio, u64),
+    Flush(u64),
+    Finish(u64),
+}
+
+async fn record_loop(
+    mut voice_rx: broadcast::Receiver<ReceivedAudio>,
+    mut stop_rx: oneshot::Receiver<()>,
+    dir: PathBuf,
+) -> Result<Vec<PathBuf>> {
+    let started = Instant::now();
+    let current_frame = || started.elapsed().as_millis() as u64 / 20;
+
+    // Opus encoding and file writes block, so they run off the runtime
+    let (tx, rx) = mpsc::channel(WRITER_BUFFER);
+    let writer = tokio::task::spawn_blocking(move || write_loop(rx, &dir));
+
+    let mut flush = tokio::time::interval(Duration::from_millis(100));
+
+    loop {
+        let msg = tokio::select! {
+            _ = &mut stop_rx => break,
+            _ = flush.tick() => WriterMsg::Flush(current_frame().saturating_sub(MIX_DELAY_FRAMES)),
+            msg = voice_rx.recv() => match msg {
+                Ok(audio) => WriterMsg::Audio(audio, current_frame()),
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    eprintln!("[RECORDER] Lagged behind, dropped {} frames", n);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        };
+        if tx.send(msg).await.is_err() {
+            // The writer failed; its error is returned below
+            break;
+        }
+    }
+
+    let _ = tx.send(WriterMsg::Finish(current_frame())).await;
+    drop(tx);
+    writer.await?
+}
+
+/// Writes the per-speaker and mixed files until `Finish` arrives
+fn write_loop(mut rx: mpsc::Receiver<WriterMsg>, dir: &Path) -> Result<Vec<PathBuf>> {
+    let mixed_path = dir.join("mixed.ogg");
+    let mut mixed = OggOpusWriter::new(BufWriter::new(File::create(&mixed_path)?))?;
+    let mut mix_buf: BTreeMap<u64, Vec<i32>> = BTreeMap::new();
+    let mut speakers: HashMap<u32, SpeakerTrack> = HashMap::new();
+    let mut now = 0;
+
+    while let Some(msg) = rx.blocking_recv() {
+        let (audio, arrived) = match msg {
+            WriterMsg::Audio(audio, arrived) => (audio, arrived),
+            WriterMsg::Flush(until) => {
+                flush_mix(&mut mixed, &mut mix_buf, until)?;
+                continue;
+            }
+            WriterMsg::Finish(at) => {
+                now = at;
+                break;
+            }
+        };
+        now = arrived;
+
+        let track = match speakers.get_mut(&audio.ssrc) {
+            Some(track) => track,
+            None => {
+                let name = match &audio.user_id {
+                    Some(user_id) => format!("user_{}.ogg", user_id),
+                    None => format!("ssrc_{}.ogg", audio.ssrc),
+                };
+                let path = dir.join(name);
+                let writer = OggOpusWriter::new(BufWriter::new(File::create(&path)?))?;
+                speakers.entry(audio.ssrc).or_insert(SpeakerTrack {
+                    writer,
+                    path,
+                    clock: RtpClock::new(audio.timestamp, arrived),
+                })
+            }
+        };
+
+        let written = track.writer.frames();
+        let frame = match track.clock.place(audio.timestamp, arrived) {
+            Some(frame) if frame >= written => frame,
+            // Late or duplicate packet
+            _ => continue,
+        };
+        track.writer.write_silence(frame - written)?;
+        track.writer.write_frame(&audio.pcm)?;
+
+        if frame >= mixed.frames() {
+            let slot = mix_buf.entry(frame).or_insert_with(|| vec![0; FRAME_SAMPLES]);
+            for (acc, &s) in slot.iter_mut().zip(audio.pcm.iter()) {
+                *acc += s as i32;
+            }
+        }
+    }
+
+    let until = mix_buf.keys().next_back().copied().unwrap_or(0).max(now);
+    flush_mix(&mut mixed, &mut mix_buf, until)?;
+
+    let mut files = vec![mixed_path];
+    mixed.finish()?;
+    for (_, track) in speakers {
+        track.writer.finish()?;
+        files.push(track.path);
+    }
+
+    Ok(files)
+}
+
+/// Writes all mixed frames before `until`, filling gaps with silence
+fn flush_mix(
+    mixed: &mut FileWriter,
+    mix_buf: &mut BTreeMap<u64, Vec<i32>>,
+    until: u64,
+) -> Result<()> {
+    while mixed.frames() < until {
+        let frame = mixed.frames();
+        match mix_buf.remove(&frame) {
+            Some(sum) => {
+                let pcm: Vec<i16> = sum
+                    .iter()
+                    .map(|&s| s.clamp(i16::MIN as i32, i16::MAX as i32) as i16)
+                    .collect();
+                mixed.write_frame(&pcm)?;
+            }
+            None => {
+                // Jump straight to the next frame that has audio
+                let next = mix_buf.keys().next().copied().unwrap_or(until).min(until);
+                mixed.write_silence((next - frame).max(1))?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use crate::discord_voice_api::voice::player::AudioFrame;
+
+const CHANNELS: usize = 2;
+const FRAME_SAMPLES: usize = 960; // per channel
+
+/// Turns an arbitrary stream of s16le stereo bytes into 20 ms frames,
+/// played back at `rate` (pitch and tempo change together, like a sped-up record).
+/// At rate 1.0 frames pass through unchanged.
+pub struct Resampler {
+    samples: Vec<i16>, // interleaved
+    leftover: Option<u8>,
+    pos: f64,          // fractional read position in stereo frames
+    consumed: u64,     // stereo frames drained since the last clear
+}
+
+impl Resampler {
+    pub fn new() -> Self {
+        Self {
+            samples: Vec::with_capacity(FRAME_SAMPLES * CHANNELS * 2),
+            leftover: None,
+            pos: 0.0,
+            consumed: 0,
+        }
+    }
+
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        let mut bytes = bytes;
+        if let Some(lo) = self.leftover.take() {
+            match bytes.first() {
+                Some(&hi) => {
+                    self.samples.push(i16::from_le_bytes([lo, hi]));
+                    bytes = &bytes[1..];
+                }
+                None => {
+                    self.leftover = Some(lo);
+                    return;
+                }
+            }
+        }
+
+        let chunks = bytes.chunks_exact(2);
+        self.leftover = chunks.remainder().first().copied();
+        self.samples
+            .extend(chunks.map(|b| i16::from_le_bytes([b[0], b[1]])));
+    }
+
+    fn frames_available(&self) -> usize {
+        self.samples.len() / CHANNELS
+    }
+
+    /// Produces the next 20 ms frame, or `None` if more input is needed
+    pub fn next_frame(&mut self, rate: f64) -> Option<AudioFrame> {
+        let last_pos = self.pos + (FRAME_SAMPLES - 1) as f64 * rate;
+        if (last_pos.floor() as usize) + 1 >= self.frames_available() {
+            return None;
+        }
+
+        let mut out = Vec::with_capacity(FRAME_SAMPLES * CHANNELS);
+        for i in 0..FRAME_SAMPLES {
+            let p = self.pos + i as f64 * rate;
+            let i0 = p.floor() as usize;
+            let frac = p - i0 as f64;
+            for ch in 0..CHANNELS {
+                let a = self.samples[i0 * CHANNELS + ch] as f64;
+                let b = self.samples[(i0 + 1) * CHANNELS + ch] as f64;
+                out.push((a + (b - a) * frac) as i16);
+            }
+        }
+
+        self.pos += FRAME_SAMPLES as f64 * rate;
+        let consumed = self.pos.floor() as usize;
+        self.samples.drain(..consumed * CHANNELS);
+        self.pos -= consumed as f64;
+        self.consumed += consumed as u64;
+
+        Some(out)
+    }
+
+    /// Input sample (per channel, counted since the last clear) the next
+    /// frame starts at, if it lies exactly on one. At rate 1.0 that frame is
+    /// then a verbatim copy of the input.
+    pub fn position(&self) -> Option<u64> {
+        (self.pos == 0.0).then_some(self.consumed)
+    }
+
+    /// Drops input up to the next multiple of a frame, so frames start where
+    /// the source's 20 ms packets do again after playing at another rate.
+    /// `false` if the input ran out first, call again after pushing more.
+    pub fn align(&mut self) -> bool {
+        let at = self.consumed as f64 + self.pos;
+        let grid = FRAME_SAMPLES as u64;
+        let target = (at / grid as f64).ceil() as u64 * grid;
+        let drop = ((target - self.consumed) as usize).min(self.frames_available());
+        self.samples.drain(..drop * CHANNELS);
+        self.consumed += drop as u64;
+        self.pos = 0.0;
+        self.consumed == target
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.leftover = None;
+        self.pos = 0.0;
+        self.consumed = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_silence(resampler: &mut Resampler, frames: usize) {
+        resampler.push_bytes(&vec![0u8; frames * CHANNELS * 2]);
+    }
+
+    #[test]
+    fn position_advances_by_frame_at_normal_rate() {
+        let mut r = Resampler::new();
+        push_silence(&mut r, FRAME_SAMPLES * 4);
+        assert_eq!(r.position(), Some(0));
+        r.next_frame(1.0).unwrap();
+        assert_eq!(r.position(), Some(960));
+        r.next_frame(1.0).unwrap();
+        assert_eq!(r.position(), Some(1920));
+    }
+
+    #[test]
+    fn position_follows_input_consumed_at_other_rates() {
+        let mut r = Resampler::new();
+        push_silence(&mut r, FRAME_SAMPLES * 8);
+        r.next_frame(1.25).unwrap();
+        assert_eq!(r.position(), Some(1200));
+        r.next_frame(0.8).unwrap();
+        assert_eq!(r.position(), Some(1968));
+        r.next_frame(1.01).unwrap();
+        assert_eq!(r.position(), None);
+    }
+
+    #[test]
+    fn align_returns_to_frame_grid_after_rate_change() {
+        let mut r = Resampler::new();
+        push_silence(&mut r, FRAME_SAMPLES * 8);
+        r.next_frame(1.25).unwrap();
+        assert!(r.align());
+        assert_eq!(r.position(), Some(1920));
+        r.next_frame(1.0).unwrap();
+        assert_eq!(r.position(), Some(2880));
+        // Already on the grid: nothing is dropped
+        assert!(r.align());
+        assert_eq!(r.position(), Some(2880));
+    }
+
+    #[test]
+    fn align_rounds_fractional_positions_up() {
+        let mut r = Resampler::new();
+        push_silence(&mut r, FRAME_SAMPLES * 4);
+        r.next_frame(1.01).unwrap();
+        assert_eq!(r.position(), None);
+        assert!(r.align());
+        assert_eq!(r.position(), Some(1920));
+    }
+
+    #[test]
+    fn align_waits_for_more_input() {
+        let mut r = Resampler::new();
+        push_silence(&mut r, 1300);
+        r.next_frame(1.25).unwrap();
+        // 100 of the 720 samples up to the boundary are buffered
+        assert!(!r.align());
+        push_silence(&mut r, 2000);
+        assert!(r.align());
+        assert_eq!(r.position(), Some(1920));
+        r.next_frame(1.0).unwrap();
+        assert_eq!(r.position(), Some(2880));
+    }
+}
@@ -0,0 +1,58 @@
+use anyhow::{Result, anyhow};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::process::Command;
+
+/// Opus sources (YouTube's WebM audio) can only be passed through to Discord
+/// without re-encoding by the native decoder, so only it prefers them
+#[cfg(feature = "native-decoder")]
+pub const AUDIO_FORMAT: &str = "bestaudio[acodec=opus]/bestaudio[ext=m4a]/bestaudio/best";
+#[cfg(not(feature = "native-decoder"))]
+pub const AUDIO_FORMAT: &str = "bestaudio[ext=m4a]/bestaudio/best";
+
+/// URLs this close to expiring are resolved again before playback starts
+const EXPIRY_MARGIN_SECS: u64 = 60;
+
+/// Asks yt-dlp for a fresh direct media URL of a page
+pub async fn resolve_stream_url(page_url: &str) -> Result<String> {
+    let output = Command::new("yt-dlp")
+        .arg("-f")
+        .arg(AUDIO_FORMAT)
+        .arg("-g") // get direct media URL
+        .arg(page_url)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "yt-dlp failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let stream_url = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if stream_url.is_empty() {
+        return Err(anyhow!("yt-dlp returned no stream URL"));
+    }
+    Ok(stream_url)
+}
+
+/// YouTube stream URLs carry their expiry as `expire=<unix time>`.
+/// URLs without one are assumed to stay valid.
+pub fn is_expired(stream_url: &str) -> bool {
+    let Ok(url) = reqwest::Url::parse(stream_url) else {
+        return false;
+    };
+    let Some(expire) = url
+        .query_pairs()
+        .find(|(k, _)| k == "expire")
+        .and_then(|(_, v)| v.parse::<u64>().ok())
+    else {
+        return false;
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    expire <= now + EXPIRY_MARGIN_SECS
+}
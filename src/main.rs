@@ -2,18 +2,22 @@ mod commands;
 mod discord_voice_api;
 
 use crate::discord_voice_api::DiscordVoiceApi;
+use crate::commands::play::SearchCache;
+use crate::discord_voice_api::voice::player::AudioPlayer;
 use reqwest::Client as HttpClient;
 use serde::{Deserialize, Serialize};
 use serenity::all::{
-    Command, CreateEmbed, Interaction,
+    Command, CreateActionRow, CreateAttachment, CreateEmbed, GuildId, Interaction, ShardManager,
 };
 use serenity::async_trait;
 use serenity::builder::EditInteractionResponse;
 use serenity::client::Context;
 use serenity::model::gateway::Ready;
 use serenity::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
+use tokio::task::JoinHandle;
 use tracing_subscriber::fmt::init;
 
 struct Handler;
@@ -22,9 +26,21 @@ impl TypeMapKey for HttpKey {
     type Value = HttpClient;
 }
 
+struct ShardManagerKey;
+impl TypeMapKey for ShardManagerKey {
+    type Value = Arc<ShardManager>;
+}
+
 struct BotData {
     bot_pfp_url: String,
     voice_api: Arc<DiscordVoiceApi>,
+    /// Tasks that keep a guild's `/nowplaying` message up to date
+    now_playing_updaters: tokio::sync::Mutex<HashMap<GuildId, JoinHandle<()>>>,
+    /// Per guild: the player whose track changes are announced, and the announcing task
+    announcers: tokio::sync::Mutex<HashMap<GuildId, (Weak<AudioPlayer>, JoinHandle<()>)>>,
+    /// Guilds that turned now-playing announcements off with `/announce`
+    announce_disabled: tokio::sync::Mutex<HashSet<GuildId>>,
+    search_cache: Arc<SearchCache>,
 }
 
 impl TypeMapKey for BotData {
@@ -34,6 +50,8 @@ impl TypeMapKey for BotData {
 enum CommandResponse {
     Text(String),
     Embed(CreateEmbed),
+    File(CreateEmbed, CreateAttachment),
+    Components(CreateEmbed, Vec<CreateActionRow>),
 }
 
 pub struct QuoteData;
@@ -76,6 +94,10 @@ impl EventHandler for Handler {
                     .avatar_url()
                     .unwrap(),
                 voice_api: Arc::new(DiscordVoiceApi::new()),
+                now_playing_updaters: Default::default(),
+                announcers: Default::default(),
+                announce_disabled: Default::default(),
+                search_cache: Arc::new(SearchCache::new()),
             });
         }
 
@@ -95,7 +117,26 @@ impl EventHandler for Handler {
                 commands::rand_quote::register(),
                 commands::dick_size::register(),
                 commands::roast::register(),
-                commands::bass_boost::register()
+                commands::bass_boost::register(),
+                commands::nightcore::register(),
+                commands::vaporwave::register(),
+                commands::record::register(),
+                commands::clip::register(),
+                commands::volume::register(),
+                commands::seek::register(),
+                commands::forward::register(),
+                commands::rewind::register(),
+                commands::loop_mode::register(),
+                commands::remove::register(),
+                commands::move_track::register(),
+                commands::clear::register(),
+                commands::shuffle::register(),
+                commands::playnext::register(),
+                commands::dedupe::register(),
+                commands::previous::register(),
+                commands::history::register(),
+                commands::announce::register(),
+                commands::search::register(),
             ],
         )
         .await
@@ -109,9 +150,9 @@ impl EventHandler for Handler {
             command.defer(&ctx.http).await.expect("Could not defer");
 
             let response = match command.data.name.as_str() {
-                "ping" => Some(CommandResponse::Text(commands::ping::run(
-                    &command.data.options(),
-                ))),
+                "ping" => Some(CommandResponse::Text(
+                    commands::ping::run(&ctx, &command).await,
+                )),
                 "play" => Some(CommandResponse::Text(
                     commands::play::run(&ctx, &command, &command.data.options()).await,
                 )),
@@ -133,9 +174,9 @@ impl EventHandler for Handler {
                 "neko" => Some(CommandResponse::Embed(
                     commands::neko::run(&ctx, &command).await,
                 )),
-                "nowplaying" => Some(CommandResponse::Embed(
+                "nowplaying" => Some(
                     commands::nowplaying::run(&ctx, &command, &command.data.options()).await,
-                )),
+                ),
                 "serverinfo" => Some(CommandResponse::Embed(
                     commands::serverinfo::run(&ctx, &command).await,
                 )),
@@ -151,6 +192,63 @@ impl EventHandler for Handler {
                 "bass-boost" => Some(CommandResponse::Embed(
                     commands::bass_boost::run(&ctx, &command).await,
                 )),
+                "nightcore" => Some(CommandResponse::Embed(
+                    commands::nightcore::run(&ctx, &command).await,
+                )),
+                "vaporwave" => Some(CommandResponse::Embed(
+                    commands::vaporwave::run(&ctx, &command).await,
+                )),
+                "record" => Some(CommandResponse::Embed(
+                    commands::record::run(&ctx, &command, &command.data.options()).await,
+                )),
+                "clip" => Some(
+                    commands::clip::run(&ctx, &command, &command.data.options()).await,
+                ),
+                "volume" => Some(CommandResponse::Embed(
+                    commands::volume::run(&ctx, &command, &command.data.options()).await,
+                )),
+                "seek" => Some(CommandResponse::Embed(
+                    commands::seek::run(&ctx, &command, &command.data.options()).await,
+                )),
+                "forward" => Some(CommandResponse::Embed(
+                    commands::forward::run(&ctx, &command, &command.data.options()).await,
+                )),
+                "rewind" => Some(CommandResponse::Embed(
+                    commands::rewind::run(&ctx, &command, &command.data.options()).await,
+                )),
+                "loop" => Some(CommandResponse::Embed(
+                    commands::loop_mode::run(&ctx, &command, &command.data.options()).await,
+                )),
+                "remove" => Some(CommandResponse::Embed(
+                    commands::remove::run(&ctx, &command, &command.data.options()).await,
+                )),
+                "move" => Some(CommandResponse::Embed(
+                    commands::move_track::run(&ctx, &command, &command.data.options()).await,
+                )),
+                "clear" => Some(CommandResponse::Embed(
+                    commands::clear::run(&ctx, &command).await,
+                )),
+                "shuffle" => Some(CommandResponse::Embed(
+                    commands::shuffle::run(&ctx, &command).await,
+                )),
+                "playnext" => Some(CommandResponse::Text(
+                    commands::playnext::run(&ctx, &command, &command.data.options()).await,
+                )),
+                "dedupe" => Some(CommandResponse::Embed(
+                    commands::dedupe::run(&ctx, &command).await,
+                )),
+                "previous" => Some(CommandResponse::Embed(
+                    commands::previous::run(&ctx, &command).await,
+                )),
+                "history" => Some(CommandResponse::Embed(
+                    commands::history::run(&ctx, &command).await,
+                )),
+                "announce" => Some(CommandResponse::Embed(
+                    commands::announce::run(&ctx, &command, &command.data.options()).await,
+                )),
+                "search" => Some(
+                    commands::search::run(&ctx, &command, &command.data.options()).await,
+                ),
                 _ => Some(CommandResponse::Text("not implemented :(".to_string())),
             };
 
@@ -163,12 +261,33 @@ impl EventHandler for Handler {
                     CommandResponse::Embed(embed) => {
                         data = data.add_embed(embed);
                     }
+                    CommandResponse::File(embed, file) => {
+                        data = data.add_embed(embed).new_attachment(file);
+                    }
+                    CommandResponse::Components(embed, rows) => {
+                        data = data.add_embed(embed).components(rows);
+                    }
                 }
 
-                if let Err(why) = command.edit_response(&ctx.http, data).await {
-                    println!("Cannot respond to slash command: {why}");
+                match command.edit_response(&ctx.http, data).await {
+                    Ok(message) if command.data.name == "nowplaying" => {
+                        commands::nowplaying::keep_updated(&ctx, &command, message).await;
+                    }
+                    Ok(_) => {}
+                    Err(why) => println!("Cannot respond to slash command: {why}"),
                 }
             }
+        } else if let Interaction::Component(component) = interaction {
+            if component.data.custom_id == commands::search::MENU_ID {
+                commands::search::handle_select(&ctx, &component).await;
+            } else {
+                commands::nowplaying::handle_button(&ctx, &component).await;
+            }
+        } else if let Interaction::Autocomplete(interaction) = interaction {
+            match interaction.data.name.as_str() {
+                "play" | "playnext" => commands::play::autocomplete(&ctx, &interaction).await,
+                _ => {}
+            }
         }
     }
 }
@@ -194,6 +313,12 @@ async fn main() {
         .await
         .expect("Error while creating client");
 
+    client
+        .data
+        .write()
+        .await
+        .insert::<ShardManagerKey>(client.shard_manager.clone());
+
     if let Err(why) = client.start().await {
         println!("Client error: {:?}", why);
     }